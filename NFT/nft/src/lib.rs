@@ -14,23 +14,39 @@ NOTES:
     attach more deposit than required.
   - To prevent the deployed contract from being modified or deleted, it should not have any access
     keys on its account.
+  - Accounts with the minter role, e.g. the staking contract, can mint soulbound receipt tokens.
+    Receipts can't be transferred with `nft_transfer` or `nft_transfer_call`, nor approved;
+    only a minter can move or burn them. Receipt ids contain a `:`, which other token ids can't.
 */
+use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApproval;
+use near_contract_standards::non_fungible_token::core::{
+    NonFungibleTokenCore, NonFungibleTokenResolver,
+};
 use near_contract_standards::non_fungible_token::metadata::{
     NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC,
 };
 use near_contract_standards::non_fungible_token::NonFungibleToken;
 use near_contract_standards::non_fungible_token::{Token, TokenId};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LazyOption;
+use near_sdk::collections::{LazyOption, LookupSet, UnorderedSet};
 use near_sdk::{
-    env, near_bindgen, require, AccountId, BorshStorageKey, PanicOnDefault, Promise, PromiseOrValue,
+    assert_one_yocto, env, near_bindgen, require, AccountId, BorshStorageKey, PanicOnDefault,
+    Promise, PromiseOrValue,
 };
+use std::collections::HashMap;
+
+/// Separates the parts of a receipt id, e.g. the NFT contract and token id of a staked token.
+const RECEIPT_ID_DELIMITER: char = ':';
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     tokens: NonFungibleToken,
     metadata: LazyOption<NFTContractMetadata>,
+    /// Accounts allowed to mint, move and burn receipts.
+    minters: UnorderedSet<AccountId>,
+    /// Soulbound receipt tokens.
+    receipts: LookupSet<TokenId>,
 }
 
 const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 288 288'%3E%3Cg id='l' data-name='l'%3E%3Cpath d='M187.58,79.81l-30.1,44.69a3.2,3.2,0,0,0,4.75,4.2L191.86,103a1.2,1.2,0,0,1,2,.91v80.46a1.2,1.2,0,0,1-2.12.77L102.18,77.93A15.35,15.35,0,0,0,90.47,72.5H87.34A15.34,15.34,0,0,0,72,87.84V201.16A15.34,15.34,0,0,0,87.34,216.5h0a15.35,15.35,0,0,0,13.08-7.31l30.1-44.69a3.2,3.2,0,0,0-4.75-4.2L96.14,186a1.2,1.2,0,0,1-2-.91V104.61a1.2,1.2,0,0,1,2.12-.77l89.55,107.23a15.35,15.35,0,0,0,11.71,5.43h3.13A15.34,15.34,0,0,0,216,201.16V87.84A15.34,15.34,0,0,0,200.66,72.5h0A15.35,15.35,0,0,0,187.58,79.81Z'/%3E%3C/g%3E%3C/svg%3E";
@@ -42,6 +58,8 @@ enum StorageKey {
     TokenMetadata,
     Enumeration,
     Approval,
+    Minters,
+    Receipts,
 }
 
 #[near_bindgen]
@@ -77,6 +95,8 @@ impl Contract {
                 Some(StorageKey::Approval),
             ),
             metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            minters: UnorderedSet::new(StorageKey::Minters),
+            receipts: LookupSet::new(StorageKey::Receipts),
         }
     }
 
@@ -96,12 +116,186 @@ impl Contract {
         token_metadata: TokenMetadata,
     ) -> Token {
         assert_eq!(env::predecessor_account_id(), self.tokens.owner_id, "Unauthorized");
+        require!(
+            !token_id.contains(RECEIPT_ID_DELIMITER),
+            "Token ids containing ':' are reserved for receipts"
+        );
+        self.tokens.internal_mint(token_id, token_owner_id, Some(token_metadata))
+    }
+
+    /// Grants the minter role to `account_id`. Only the owner can call this.
+    pub fn add_minter(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.tokens.owner_id, "Unauthorized");
+        self.minters.insert(&account_id);
+    }
+
+    /// Revokes the minter role from `account_id`. Only the owner can call this.
+    pub fn remove_minter(&mut self, account_id: AccountId) {
+        assert_eq!(env::predecessor_account_id(), self.tokens.owner_id, "Unauthorized");
+        self.minters.remove(&account_id);
+    }
+
+    pub fn is_minter(&self, account_id: AccountId) -> bool {
+        self.minters.contains(&account_id)
+    }
+
+    pub fn is_receipt(&self, token_id: TokenId) -> bool {
+        self.receipts.contains(&token_id)
+    }
+
+    /// Mint a soulbound receipt with ID=`token_id` belonging to `token_owner_id`. The id must
+    /// contain a `:`. The minter has to attach enough deposit to cover the storage of the token.
+    #[payable]
+    pub fn nft_mint_receipt(
+        &mut self,
+        token_id: TokenId,
+        token_owner_id: AccountId,
+        token_metadata: TokenMetadata,
+    ) -> Token {
+        self.assert_minter();
+        require!(token_id.contains(RECEIPT_ID_DELIMITER), "Receipt ids must contain ':'");
+        self.receipts.insert(&token_id);
         self.tokens.internal_mint(token_id, token_owner_id, Some(token_metadata))
     }
+
+    /// Move the receipt `token_id` to `receiver_id`, e.g. when the position it stands for
+    /// changes hands.
+    pub fn nft_transfer_receipt(&mut self, token_id: TokenId, receiver_id: AccountId) {
+        self.assert_minter();
+        require!(self.receipts.contains(&token_id), "Token is not a receipt");
+        let owner_id = self.tokens.owner_by_id.get(&token_id).expect("Token not found");
+        self.tokens.internal_transfer(&owner_id, &receiver_id, &token_id, None, None);
+    }
+
+    /// Burn the receipt `token_id`, refunding the released storage to the minter.
+    pub fn nft_burn_receipt(&mut self, token_id: TokenId) {
+        self.assert_minter();
+        require!(self.receipts.remove(&token_id), "Token is not a receipt");
+        let initial_storage_usage = env::storage_usage();
+        let owner_id = self.tokens.owner_by_id.remove(&token_id).expect("Token not found");
+        if let Some(token_metadata_by_id) = &mut self.tokens.token_metadata_by_id {
+            token_metadata_by_id.remove(&token_id);
+        }
+        if let Some(tokens_per_owner) = &mut self.tokens.tokens_per_owner {
+            let mut token_ids = tokens_per_owner.get(&owner_id).unwrap();
+            token_ids.remove(&token_id);
+            if token_ids.is_empty() {
+                tokens_per_owner.remove(&owner_id);
+            } else {
+                tokens_per_owner.insert(&owner_id, &token_ids);
+            }
+        }
+        if let Some(approvals_by_id) = &mut self.tokens.approvals_by_id {
+            approvals_by_id.remove(&token_id);
+        }
+        if let Some(next_approval_id_by_id) = &mut self.tokens.next_approval_id_by_id {
+            next_approval_id_by_id.remove(&token_id);
+        }
+        let released = initial_storage_usage.saturating_sub(env::storage_usage());
+        if released > 0 {
+            Promise::new(env::predecessor_account_id())
+                .transfer(released as u128 * env::storage_byte_cost());
+        }
+    }
+}
+
+impl Contract {
+    fn assert_minter(&self) {
+        require!(self.minters.contains(&env::predecessor_account_id()), "Unauthorized");
+    }
+
+    fn assert_not_receipt(&self, token_id: &TokenId, message: &str) {
+        require!(!self.receipts.contains(token_id), message);
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenCore for Contract {
+    #[payable]
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        self.assert_not_receipt(&token_id, "Receipts are not transferable");
+        self.tokens.nft_transfer(receiver_id, token_id, approval_id, memo)
+    }
+
+    #[payable]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        assert_one_yocto();
+        self.assert_not_receipt(&token_id, "Receipts are not transferable");
+        self.tokens.nft_transfer_call(receiver_id, token_id, approval_id, memo, msg)
+    }
+
+    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        self.tokens.nft_token(token_id)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenResolver for Contract {
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        self.tokens.nft_resolve_transfer(
+            previous_owner_id,
+            receiver_id,
+            token_id,
+            approved_account_ids,
+        )
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenApproval for Contract {
+    /// Receipts can't be approved, so they can't be soft staked.
+    #[payable]
+    fn nft_approve(
+        &mut self,
+        token_id: TokenId,
+        account_id: AccountId,
+        msg: Option<String>,
+    ) -> Option<Promise> {
+        self.assert_not_receipt(&token_id, "Receipts can't be approved");
+        self.tokens.nft_approve(token_id, account_id, msg)
+    }
+
+    #[payable]
+    fn nft_revoke(&mut self, token_id: TokenId, account_id: AccountId) {
+        self.tokens.nft_revoke(token_id, account_id)
+    }
+
+    #[payable]
+    fn nft_revoke_all(&mut self, token_id: TokenId) {
+        self.tokens.nft_revoke_all(token_id)
+    }
+
+    fn nft_is_approved(
+        &self,
+        token_id: TokenId,
+        approved_account_id: AccountId,
+        approval_id: Option<u64>,
+    ) -> bool {
+        self.tokens.nft_is_approved(token_id, approved_account_id, approval_id)
+    }
 }
 
-near_contract_standards::impl_non_fungible_token_core!(Contract, tokens);
-near_contract_standards::impl_non_fungible_token_approval!(Contract, tokens);
 near_contract_standards::impl_non_fungible_token_enumeration!(Contract, tokens);
 
 #[near_bindgen]
@@ -115,11 +309,11 @@ impl NonFungibleTokenMetadataProvider for Contract {
 mod tests {
     use near_sdk::test_utils::{accounts, VMContextBuilder};
     use near_sdk::testing_env;
-    use std::collections::HashMap;
 
     use super::*;
 
     const MINT_STORAGE_COST: u128 = 5870000000000000000000;
+    const RECEIPT_STORAGE_COST: u128 = 6070000000000000000000;
 
     fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
         let mut builder = VMContextBuilder::new();
@@ -327,4 +521,109 @@ mod tests {
             .build());
         assert!(!contract.nft_is_approved(token_id.clone(), accounts(1), Some(1)));
     }
+
+    #[test]
+    #[should_panic(expected = "Unauthorized")]
+    fn test_mint_receipt_not_minter() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(0));
+
+        testing_env!(context
+            .attached_deposit(RECEIPT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.nft_mint_receipt("nft:0".to_string(), accounts(2), sample_token_metadata());
+    }
+
+    #[test]
+    #[should_panic(expected = "Receipts are not transferable")]
+    fn test_transfer_receipt() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(0));
+        contract.add_minter(accounts(1));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(RECEIPT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        let token_id = "nft:0".to_string();
+        contract.nft_mint_receipt(token_id.clone(), accounts(0), sample_token_metadata());
+        assert!(contract.is_receipt(token_id.clone()));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_transfer(accounts(3), token_id, None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Receipts can't be approved")]
+    fn test_approve_receipt() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(0));
+        contract.add_minter(accounts(1));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(RECEIPT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        let token_id = "nft:0".to_string();
+        contract.nft_mint_receipt(token_id.clone(), accounts(0), sample_token_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(150000000000000000000)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_approve(token_id, accounts(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token ids containing ':' are reserved for receipts")]
+    fn test_mint_receipt_id() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(0));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_mint("nft:0".to_string(), accounts(0), sample_token_metadata());
+    }
+
+    #[test]
+    fn test_burn_receipt() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new_default_meta(accounts(0));
+        contract.add_minter(accounts(1));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(RECEIPT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        let token_id = "nft:0".to_string();
+        contract.nft_mint_receipt(token_id.clone(), accounts(0), sample_token_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.nft_transfer_receipt(token_id.clone(), accounts(3));
+        assert_eq!(contract.nft_token(token_id.clone()).unwrap().owner_id, accounts(3));
+        contract.nft_burn_receipt(token_id.clone());
+        assert_eq!(contract.nft_token(token_id.clone()), None);
+        assert!(!contract.is_receipt(token_id));
+    }
 }
@@ -0,0 +1,50 @@
+use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApprovalReceiver;
+
+use crate::*;
+
+/// Approving the staking contract on an NFT soft stakes the token: it stays in the owner's
+/// wallet and earns rewards for as long as the owner keeps both the token and the approval.
+#[near_bindgen]
+impl NonFungibleTokenApprovalReceiver for CrossContract {
+    /// Records a soft stake of `token_id` for `owner_id`. The calling NFT contract vouches for
+    /// the owner, so a soft stake left behind by a previous owner is replaced, while a new
+    /// approval by the same owner keeps the stake and its rewards. `msg` takes a [`StakeMsg`].
+    fn nft_on_approve(
+        &mut self,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+        msg: String,
+    ) -> PromiseOrValue<String> {
+        let nft_contract_id = env::predecessor_account_id();
+        let contract_token_id = contract_token_id(&nft_contract_id, &token_id);
+        let StakeMsg { referrer_id } = StakeMsg::parse(&msg);
+        if let Some(mut stake) = self.stakes.get(&contract_token_id) {
+            if stake.approval_id.is_none() {
+                StakingError::TokenAlreadyStaked.panic();
+            }
+            if stake.owner_id == owner_id {
+                stake.approval_id = Some(approval_id);
+                self.stakes.insert(&contract_token_id, &stake);
+                return PromiseOrValue::Value(token_id);
+            }
+            self.internal_remove_stake(&stake.owner_id, &contract_token_id);
+        }
+        self.internal_set_referrer(&owner_id, referrer_id);
+        self.internal_add_stake(&owner_id, &nft_contract_id, &token_id, Some(approval_id));
+        PromiseOrValue::Value(token_id)
+    }
+}
+
+impl CrossContract {
+    /// Whether `token` is still owned by the staker and approved for this contract with the
+    /// approval the soft stake was recorded with.
+    pub(crate) fn is_soft_stake_valid(&self, stake: &Stake, token: &Token) -> bool {
+        token.owner_id == stake.owner_id
+            && token
+                .approved_account_ids
+                .as_ref()
+                .and_then(|approvals| approvals.get(&env::current_account_id()).copied())
+                == stake.approval_id
+    }
+}
@@ -0,0 +1,84 @@
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+
+use crate::*;
+
+const MSG_BONUS: &str = "bonus";
+const MSG_REWARDS: &str = "rewards";
+
+/// Bonus drops are sent with `ft_transfer_call` and `msg` `"bonus"` by a reward manager, and
+/// split evenly between all confirmed stakes, i.e. tokens that reached the contract or were soft
+/// staked; a stake whose transfer is still pending gets no share, nor does it once rolled back.
+/// As bonuses are paid out with the other rewards, drops are only accepted while rewards are
+/// paid in the reward token. Instead of crediting every staker, the contract adds each token's
+/// share to a running total, and credits an account what its tokens earned since it last
+/// settled whenever its stakes change or it claims. What can't be split evenly is carried over
+/// to the next drop.
+///
+/// Anyone can also fund the reward pool by sending the reward token with `msg` `"rewards"`.
+#[near_bindgen]
+impl FungibleTokenReceiver for CrossContract {
+    /// Adds `amount` of the reward token to the reward pool, or distributes it to the current
+    /// stakers for a bonus drop. Returns the whole bonus if nothing is staked.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        if env::predecessor_account_id() != self.ft_account {
+            StakingError::WrongToken.panic();
+        }
+        if msg == MSG_REWARDS {
+            self.ft_reward_pool += amount.0;
+            return PromiseOrValue::Value(U128(0));
+        }
+        if msg != MSG_BONUS {
+            StakingError::InvalidMsg.panic();
+        }
+        self.assert_account_role(&sender_id, Role::RewardManager);
+        if self.config.reward_mode != RewardMode::Ft {
+            StakingError::BonusNotAccepted.panic();
+        }
+        let total_stakes = self.internal_total_votes() as u128;
+        if total_stakes == 0 {
+            return PromiseOrValue::Value(amount);
+        }
+        let distributable = amount.0 + self.bonus_remainder;
+        self.bonus_per_stake += distributable / total_stakes;
+        self.bonus_remainder = distributable % total_stakes;
+        self.ft_reward_pool += amount.0;
+        StakingEvent::BonusDistributed(&[BonusData {
+            amount,
+            total_stakes: U64(total_stakes as u64),
+        }])
+        .emit();
+        PromiseOrValue::Value(U128(0))
+    }
+}
+
+#[near_bindgen]
+impl CrossContract {
+    /// Bonus earned by the stakes of `account_id` that isn't in its pending rewards yet.
+    pub fn get_unsettled_bonus(&self, account_id: AccountId) -> U128 {
+        self.internal_unsettled_bonus(&account_id).into()
+    }
+}
+
+impl CrossContract {
+    fn internal_unsettled_bonus(&self, account_id: &AccountId) -> u128 {
+        let staked_count = self.internal_votes(account_id);
+        let settled_at = self.bonus_settled_at.get(account_id).unwrap_or(0);
+        staked_count as u128 * (self.bonus_per_stake - settled_at)
+    }
+
+    /// Adds the unsettled bonus of `account_id` to its pending rewards. Must run before its
+    /// number of confirmed stakes changes.
+    pub(crate) fn internal_settle_bonus(&mut self, account_id: &AccountId) {
+        let bonus = self.internal_unsettled_bonus(account_id);
+        self.internal_add_pending_reward(account_id, bonus);
+        if self.bonus_per_stake > 0 {
+            self.bonus_settled_at
+                .insert(account_id, &self.bonus_per_stake);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use near_sdk::serde_json;
+
+use crate::*;
+
+/// Claims that depend on other contracts: soft stakes are checked with `nft_token` and, when
+/// boost tiers are configured, the staker's balance of the reward FT is read with
+/// `ft_balance_of`. The reward is distributed once the results are in.
+#[near_bindgen]
+impl CrossContract {
+    /// Takes `pending` and `accrued` back out of the pending rewards of `owner_id`, adds the
+    /// rewards of the soft stakes that are still valid to `accrued`, boosts it by the tier
+    /// reached by the FT balance, if `boosted`, and distributes it with `pending`. Soft stakes
+    /// whose token moved or whose approval changed are voided without reward; those that
+    /// couldn't be checked are left for the next claim. A balance that couldn't be read gets no
+    /// boost.
+    #[private]
+    pub fn resolve_checked_claim(
+        &mut self,
+        owner_id: AccountId,
+        contract_token_ids: Vec<ContractTokenId>,
+        accrued: U128,
+        pending: U128,
+        instant: bool,
+        boosted: bool,
+    ) {
+        let first_token_result = boosted as u64;
+        require!(
+            env::promise_results_count() == first_token_result + contract_token_ids.len() as u64,
+            "Contract expected a result for every check"
+        );
+        let multiplier_bps = match boosted.then(|| env::promise_result(0)) {
+            Some(PromiseResult::Successful(value)) => serde_json::from_slice::<U128>(&value)
+                .map_or(config::NO_BOOST_BPS, |balance| {
+                    self.config.boost_multiplier_bps(balance.0)
+                }),
+            _ => config::NO_BOOST_BPS,
+        };
+        // Another claim may have paid out part of them meanwhile, which is taken from `accrued`
+        // first.
+        let held = self.internal_take_pending_reward(&owner_id, pending.0 + accrued.0);
+        let pending = held.min(pending.0);
+        let mut accrued = held - pending;
+        let now = env::block_timestamp();
+        for (index, contract_token_id) in contract_token_ids.iter().enumerate() {
+            let mut stake = match self.stakes.get(contract_token_id) {
+                Some(stake) if stake.owner_id == owner_id && stake.approval_id.is_some() => stake,
+                // Unstaked or replaced while the check was in flight.
+                _ => continue,
+            };
+            let token = match env::promise_result(first_token_result + index as u64) {
+                PromiseResult::Successful(value) => serde_json::from_slice::<Option<Token>>(&value)
+                    .ok()
+                    .flatten(),
+                _ => continue,
+            };
+            if matches!(&token, Some(token) if self.is_soft_stake_valid(&stake, token)) {
+                accrued += self.internal_accrued(&stake, now);
+                stake.claimed_at = now;
+                self.stakes.insert(contract_token_id, &stake);
+            } else {
+                self.internal_remove_stake(&owner_id, contract_token_id);
+            }
+        }
+        let accrued = accrued * multiplier_bps as u128 / config::NO_BOOST_BPS as u128;
+        self.internal_pay_referral(&owner_id, accrued);
+        let reward = pending + accrued;
+        if reward == self.internal_claim_fee(reward) {
+            self.internal_add_pending_reward(&owner_id, reward);
+        } else {
+            // Kept pending if the NEAR pool can't cover it.
+            self.internal_distribute_reward(owner_id, reward, instant, multiplier_bps);
+        }
+    }
+}
+
+impl CrossContract {
+    /// Whether a claim by an account with `stake_count` stakes has to read its FT balance.
+    pub(crate) fn is_boost_enabled(&self, stake_count: usize) -> bool {
+        stake_count > 0 && !self.config.boost_tiers.is_empty()
+    }
+
+    /// Gas needed by [`Self::internal_checked_claim`].
+    pub(crate) fn gas_for_checked_claim(&self, soft_stake_count: usize, boosted: bool) -> Gas {
+        Gas(soft_stake_count as u64 * GAS_FOR_NFT_TOKEN.0
+            + boosted as u64 * GAS_FOR_FT_BALANCE_OF.0
+            + self.gas_for_resolve_checked_claim(soft_stake_count).0)
+    }
+
+    /// Gas `resolve_checked_claim` needs to settle `soft_stake_count` soft stakes, any of which
+    /// may be voided and refresh the active sets, and pay out the reward.
+    fn gas_for_resolve_checked_claim(&self, soft_stake_count: usize) -> Gas {
+        Gas(GAS_FOR_RESOLVE_CHECKED_CLAIM.0
+            + soft_stake_count as u64 * (GAS_FOR_RESOLVE_BATCH_ITEM.0 + self.gas_for_set_refresh()))
+    }
+
+    /// Checks every soft stake with `nft_token` and reads the FT balance of `owner_id` if
+    /// `boosted`, then distributes `accrued`, boosted, and `pending` along with what the valid
+    /// soft stakes earned. Both amounts must be in the pending rewards of `owner_id` meanwhile.
+    pub(crate) fn internal_checked_claim(
+        &self,
+        owner_id: AccountId,
+        soft_stakes: Vec<Stake>,
+        accrued: u128,
+        pending: u128,
+        instant: bool,
+        boosted: bool,
+    ) -> Promise {
+        let gas_for_resolve = self.gas_for_resolve_checked_claim(soft_stakes.len());
+        let contract_token_ids = soft_stakes
+            .iter()
+            .map(|stake| contract_token_id(&stake.nft_contract_id, &stake.staked_id))
+            .collect();
+        let balance_check = if boosted {
+            Some(ftext::ft_balance_of(
+                owner_id.clone(),
+                self.ft_account.clone(),
+                0,
+                GAS_FOR_FT_BALANCE_OF,
+            ))
+        } else {
+            None
+        };
+        balance_check
+            .into_iter()
+            .chain(soft_stakes.into_iter().map(|stake| {
+                nftext::nft_token(stake.staked_id, stake.nft_contract_id, 0, GAS_FOR_NFT_TOKEN)
+            }))
+            .reduce(Promise::and)
+            .unwrap()
+            .then(ext_self::resolve_checked_claim(
+                owner_id,
+                contract_token_ids,
+                accrued.into(),
+                pending.into(),
+                instant,
+                boosted,
+                env::current_account_id(),
+                0,
+                gas_for_resolve,
+            ))
+    }
+}
@@ -0,0 +1,244 @@
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Roughly one whole token (24 decimals) per staked NFT per day.
+const DEFAULT_REWARD_RATE: u128 = 1_000_000_000_000_000_000_000_000 / 86_400;
+const DEFAULT_MAX_STAKES_PER_ACCOUNT: u32 = 10;
+const DEFAULT_MAX_SETS: u32 = 10;
+/// 100% in basis points.
+pub(crate) const MAX_BPS: u16 = 10_000;
+/// Multiplier of a boost tier, in basis points, that leaves rewards unchanged.
+pub(crate) const NO_BOOST_BPS: u32 = MAX_BPS as u32;
+/// Highest multiplier a boost tier can have, in basis points.
+const MAX_BOOST_BPS: u32 = 10 * NO_BOOST_BPS;
+
+/// Reward multiplier for stakers holding at least `min_balance` of the reward FT.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BoostTier {
+    pub min_balance: U128,
+    /// Multiplier in basis points, 10000 being 1x.
+    pub multiplier_bps: u32,
+}
+
+/// How many more tokens an account can stake.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CapacityView {
+    pub account_staked: u32,
+    pub max_stakes_per_account: u32,
+    pub total_staked: U64,
+    pub max_total_stakes: Option<U64>,
+    /// Tokens the account can still stake under both caps.
+    pub available: u32,
+}
+
+/// Token rewards are paid out in.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum RewardMode {
+    /// The FT of `ft_account`.
+    Ft,
+    /// NEAR, from the pool funded with `deposit_near_rewards`.
+    Near,
+}
+
+/// Owner-adjustable parameters of the staking contract.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Config {
+    /// Reward accrued by each staked token per second, in the smallest FT unit. Changed through
+    /// `propose_change`.
+    pub reward_rate: U128,
+    /// Time in nanoseconds a token has to stay staked before it can be unstaked.
+    pub min_lock_duration: U64,
+    /// Time in nanoseconds a new stake waits before it starts to earn rewards. Changing it only
+    /// affects later stakes.
+    pub reward_warmup: U64,
+    /// Maximum number of tokens a single account can have staked at once.
+    pub max_stakes_per_account: u32,
+    /// Maximum number of tokens staked across all accounts, unlimited if unset.
+    pub max_total_stakes: Option<U64>,
+    /// Maximum number of stake sets. Every stake, unstake and claim checks the account's stakes
+    /// against all of them, so this bounds the gas they need.
+    pub max_sets: u32,
+    /// Share of every claim kept in the treasury, in basis points. Changed through
+    /// `propose_change`.
+    pub claim_fee_bps: u16,
+    /// NFT contracts, besides `nft_account`, whose tokens can be staked.
+    pub allowed_collections: Vec<AccountId>,
+    /// NFT contract minting a soulbound receipt for every custodial stake, with this contract as
+    /// a minter. Changing it doesn't move receipts already minted.
+    pub receipt_contract_id: Option<AccountId>,
+    /// Time in nanoseconds before claimed rewards start to unlock.
+    pub vesting_cliff: U64,
+    /// Time in nanoseconds over which claimed rewards unlock linearly. Rewards are paid out on
+    /// claim if zero.
+    pub vesting_duration: U64,
+    /// Share of the rewards given up by claiming them instantly while vesting is on, in basis
+    /// points. Changed through `propose_change`.
+    pub instant_claim_penalty_bps: u16,
+    /// Share of a referred account's rewards credited to its referrer, in basis points. Changed
+    /// through `propose_change`.
+    pub referral_bps: u16,
+    /// Multipliers applied to the rewards accrued by stakers holding enough of the reward FT at
+    /// claim time, by increasing `min_balance`. The highest tier reached applies.
+    pub boost_tiers: Vec<BoostTier>,
+    /// Time in nanoseconds a proposed change waits before it can be executed. Changed through
+    /// `propose_change`.
+    pub timelock_delay: U64,
+    /// Changed through `propose_change`.
+    pub reward_mode: RewardMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            reward_rate: U128(DEFAULT_REWARD_RATE),
+            min_lock_duration: U64(0),
+            reward_warmup: U64(0),
+            max_stakes_per_account: DEFAULT_MAX_STAKES_PER_ACCOUNT,
+            max_total_stakes: None,
+            max_sets: DEFAULT_MAX_SETS,
+            claim_fee_bps: 0,
+            allowed_collections: Vec::new(),
+            receipt_contract_id: None,
+            vesting_cliff: U64(0),
+            vesting_duration: U64(0),
+            instant_claim_penalty_bps: 0,
+            referral_bps: 0,
+            boost_tiers: Vec::new(),
+            timelock_delay: U64(0),
+            reward_mode: RewardMode::Ft,
+        }
+    }
+}
+
+impl Config {
+    /// Panics with [`StakingError::InvalidConfig`] if any parameter is out of range.
+    pub fn assert_valid(&self) {
+        if self.max_stakes_per_account == 0 {
+            StakingError::InvalidConfig("max_stakes_per_account must be positive").panic();
+        }
+        if self.max_total_stakes == Some(U64(0)) {
+            StakingError::InvalidConfig("max_total_stakes must be positive").panic();
+        }
+        if self.claim_fee_bps > MAX_BPS {
+            StakingError::InvalidConfig("claim_fee_bps can't exceed 10000").panic();
+        }
+        if self.vesting_cliff.0 > self.vesting_duration.0 {
+            StakingError::InvalidConfig("vesting_cliff can't exceed vesting_duration").panic();
+        }
+        if self.instant_claim_penalty_bps > MAX_BPS {
+            StakingError::InvalidConfig("instant_claim_penalty_bps can't exceed 10000").panic();
+        }
+        if self.referral_bps > MAX_BPS {
+            StakingError::InvalidConfig("referral_bps can't exceed 10000").panic();
+        }
+        if self
+            .boost_tiers
+            .windows(2)
+            .any(|tiers| tiers[0].min_balance.0 >= tiers[1].min_balance.0)
+        {
+            StakingError::InvalidConfig("boost_tiers must be sorted by increasing min_balance")
+                .panic();
+        }
+        if self
+            .boost_tiers
+            .iter()
+            .any(|tier| tier.multiplier_bps < NO_BOOST_BPS || tier.multiplier_bps > MAX_BOOST_BPS)
+        {
+            StakingError::InvalidConfig("boost multiplier_bps must be between 10000 and 100000")
+                .panic();
+        }
+    }
+
+    /// This config with the reward parameters of `other`.
+    fn with_reward_params_of(&self, other: &Config) -> Config {
+        Config {
+            reward_rate: other.reward_rate,
+            reward_warmup: other.reward_warmup,
+            claim_fee_bps: other.claim_fee_bps,
+            vesting_cliff: other.vesting_cliff,
+            vesting_duration: other.vesting_duration,
+            instant_claim_penalty_bps: other.instant_claim_penalty_bps,
+            referral_bps: other.referral_bps,
+            boost_tiers: other.boost_tiers.clone(),
+            reward_mode: other.reward_mode,
+            ..self.clone()
+        }
+    }
+
+    /// Multiplier in basis points for stakers holding `balance` of the reward FT.
+    pub(crate) fn boost_multiplier_bps(&self, balance: u128) -> u32 {
+        self.boost_tiers
+            .iter()
+            .rev()
+            .find(|tier| tier.min_balance.0 <= balance)
+            .map_or(NO_BOOST_BPS, |tier| tier.multiplier_bps)
+    }
+}
+
+#[near_bindgen]
+impl CrossContract {
+    /// Replaces the contract configuration. Changing the reward parameters takes a reward
+    /// manager, changing the others an operator. The timelocked parameters can't be changed
+    /// here, only through `propose_change`.
+    #[payable]
+    pub fn update_config(&mut self, config: Config) {
+        assert_one_yocto();
+        if config.reward_rate != self.config.reward_rate
+            || config.claim_fee_bps != self.config.claim_fee_bps
+            || config.instant_claim_penalty_bps != self.config.instant_claim_penalty_bps
+            || config.referral_bps != self.config.referral_bps
+            || config.reward_mode != self.config.reward_mode
+            || config.timelock_delay != self.config.timelock_delay
+        {
+            StakingError::InvalidConfig(
+                "reward_rate, claim_fee_bps, instant_claim_penalty_bps, referral_bps, reward_mode \
+                 and timelock_delay change through propose_change",
+            )
+            .panic();
+        }
+        let changes_rewards = self.config.with_reward_params_of(&config) != self.config;
+        if changes_rewards {
+            self.assert_role(Role::RewardManager);
+        }
+        if !changes_rewards || config.with_reward_params_of(&self.config) != self.config {
+            self.assert_role(Role::Operator);
+        }
+        config.assert_valid();
+        self.config = config;
+    }
+
+    pub fn get_config(&self) -> Config {
+        self.config.clone()
+    }
+
+    /// Tokens `account_id` has staked and can still stake, per account and in total.
+    pub fn get_capacity(&self, account_id: AccountId) -> CapacityView {
+        let account_staked = self
+            .stakes_per_owner
+            .get(&account_id)
+            .map_or(0, |ids| ids.len());
+        let total_staked = self.stakes.len();
+        let account_available =
+            (self.config.max_stakes_per_account as u64).saturating_sub(account_staked);
+        let available = match self.config.max_total_stakes {
+            Some(max) => account_available.min(max.0.saturating_sub(total_staked)),
+            None => account_available,
+        };
+        CapacityView {
+            account_staked: account_staked as u32,
+            max_stakes_per_account: self.config.max_stakes_per_account,
+            total_staked: U64(total_staked),
+            max_total_stakes: self.config.max_total_stakes,
+            available: available as u32,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::*;
+
+/// The owner can deny accounts, which then can neither stake nor claim, and block tokens, e.g.
+/// reported as stolen, which then can't be staked. Existing stakes are left in place and can
+/// always be unstaked.
+#[near_bindgen]
+impl CrossContract {
+    /// Adds `account_id` to the denylist. Only operators can call this.
+    #[payable]
+    pub fn deny_account(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_role(Role::Operator);
+        if self.denied_accounts.insert(&account_id) {
+            StakingEvent::AccountDenied(&[AccountData {
+                account_id: &account_id,
+            }])
+            .emit();
+        }
+    }
+
+    /// Removes `account_id` from the denylist. Only operators can call this.
+    #[payable]
+    pub fn allow_account(&mut self, account_id: AccountId) {
+        assert_one_yocto();
+        self.assert_role(Role::Operator);
+        if self.denied_accounts.remove(&account_id) {
+            StakingEvent::AccountAllowed(&[AccountData {
+                account_id: &account_id,
+            }])
+            .emit();
+        }
+    }
+
+    /// Adds `token_id` of `nft_contract_id` (`nft_account` if omitted) to the blocklist. Only
+    /// operators can call this.
+    #[payable]
+    pub fn block_token(&mut self, token_id: TokenId, nft_contract_id: Option<AccountId>) {
+        assert_one_yocto();
+        self.assert_role(Role::Operator);
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        if self
+            .blocked_tokens
+            .insert(&contract_token_id(&nft_contract_id, &token_id))
+        {
+            StakingEvent::TokenBlocked(&[TokenData {
+                nft_contract_id: &nft_contract_id,
+                token_id: &token_id,
+            }])
+            .emit();
+        }
+    }
+
+    /// Removes `token_id` of `nft_contract_id` (`nft_account` if omitted) from the blocklist.
+    /// Only operators can call this.
+    #[payable]
+    pub fn unblock_token(&mut self, token_id: TokenId, nft_contract_id: Option<AccountId>) {
+        assert_one_yocto();
+        self.assert_role(Role::Operator);
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        if self
+            .blocked_tokens
+            .remove(&contract_token_id(&nft_contract_id, &token_id))
+        {
+            StakingEvent::TokenUnblocked(&[TokenData {
+                nft_contract_id: &nft_contract_id,
+                token_id: &token_id,
+            }])
+            .emit();
+        }
+    }
+
+    pub fn is_account_denied(&self, account_id: AccountId) -> bool {
+        self.denied_accounts.contains(&account_id)
+    }
+
+    pub fn is_token_blocked(&self, token_id: TokenId, nft_contract_id: Option<AccountId>) -> bool {
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        self.blocked_tokens
+            .contains(&contract_token_id(&nft_contract_id, &token_id))
+    }
+
+    /// Denied accounts, `limit` of them (all if omitted) starting at `from_index`.
+    pub fn get_denied_accounts(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<AccountId> {
+        paginate(self.denied_accounts.iter(), from_index, limit)
+    }
+
+    /// Blocked tokens as `"<nft_contract_id>:<token_id>"`, `limit` of them (all if omitted)
+    /// starting at `from_index`.
+    pub fn get_blocked_tokens(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<ContractTokenId> {
+        paginate(self.blocked_tokens.iter(), from_index, limit)
+    }
+}
+
+pub(crate) fn paginate<T>(
+    items: impl Iterator<Item = T>,
+    from_index: Option<U64>,
+    limit: Option<u32>,
+) -> Vec<T> {
+    items
+        .skip(from_index.map_or(0, |index| index.0 as usize))
+        .take(limit.map_or(usize::MAX, |limit| limit as usize))
+        .collect()
+}
+
+impl CrossContract {
+    /// Panics with [`StakingError::AccountDenied`] if `account_id` is on the denylist.
+    pub(crate) fn assert_not_denied(&self, account_id: &AccountId) {
+        if self.denied_accounts.contains(account_id) {
+            StakingError::AccountDenied.panic();
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use near_sdk::{env, Gas};
+use std::fmt;
+
+/// Errors raised by the staking contract.
+///
+/// Every variant carries a stable code (`E001`, `E002`, ...) which is always the first token of
+/// the panic message, so clients can match on the code without depending on the wording.
+/// Codes are never reused or renumbered; new variants get the next free code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StakingError {
+    /// The caller has no staked tokens at all.
+    NoStakes,
+    /// The requested token is not staked by the caller.
+    TokenNotStaked,
+    /// The token is already staked.
+    TokenAlreadyStaked,
+    /// Not enough prepaid gas for the cross-contract calls the method has to make.
+    NotEnoughGas { required: Gas },
+    /// The caller is not allowed to call this method.
+    Unauthorized,
+    /// A configuration parameter is out of range.
+    InvalidConfig(&'static str),
+    /// Tokens of this NFT contract can't be staked.
+    CollectionNotAllowed,
+    /// The account already has the maximum number of tokens staked.
+    TooManyStakes { max: u32 },
+    /// A token hasn't been staked for the minimum lock duration yet.
+    StakeLocked,
+    /// There are no rewards to pay out.
+    NothingToClaim,
+    /// A batch call was given no tokens.
+    EmptyBatch,
+    /// Soft stakes stay with the token's owner and can't be transferred.
+    SoftStakeNotTransferable,
+    /// The `msg` of a transfer or approval isn't a valid `StakeMsg`.
+    InvalidMsg,
+    /// The treasury holds less than the requested amount.
+    InsufficientTreasury,
+    /// The contract already holds the maximum number of staked tokens.
+    PoolFull { max: u64 },
+    /// The account is on the denylist.
+    AccountDenied,
+    /// The token is on the blocklist.
+    TokenBlocked,
+    /// The contract is paused.
+    Paused,
+    /// There is no pending change with this id.
+    ChangeNotFound,
+    /// The pending change can't be executed before its delay has passed.
+    TimelockNotExpired,
+    /// Only the reward token can be sent to the contract.
+    WrongToken,
+    /// The NEAR reward pool can't cover the payout.
+    InsufficientRewardPool,
+    /// The contract doesn't hold the requested amount beyond the reward pool.
+    InsufficientExcess,
+    /// The token is on its way back to its owner.
+    StakeUnstaking,
+    /// Receipts of stakes can't be staked themselves.
+    ReceiptNotStakeable,
+    /// The token hasn't reached the contract yet.
+    StakePending,
+    /// The reward mode can't change while rewards are owed in the current one.
+    RewardsOutstanding,
+    /// The token isn't held by the contract.
+    TokenNotHeld,
+    /// The FT balance of the contract couldn't be read.
+    BalanceUnavailable,
+    /// Bonus drops are only accepted while rewards are paid in the reward token.
+    BonusNotAccepted,
+}
+
+impl StakingError {
+    /// Stable error code, e.g. `"E001"`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            StakingError::NoStakes => "E001",
+            StakingError::TokenNotStaked => "E002",
+            StakingError::TokenAlreadyStaked => "E003",
+            StakingError::NotEnoughGas { .. } => "E004",
+            StakingError::Unauthorized => "E005",
+            StakingError::InvalidConfig(_) => "E006",
+            StakingError::CollectionNotAllowed => "E007",
+            StakingError::TooManyStakes { .. } => "E008",
+            StakingError::StakeLocked => "E009",
+            StakingError::NothingToClaim => "E010",
+            StakingError::EmptyBatch => "E011",
+            StakingError::SoftStakeNotTransferable => "E012",
+            StakingError::InvalidMsg => "E013",
+            StakingError::InsufficientTreasury => "E014",
+            StakingError::PoolFull { .. } => "E015",
+            StakingError::AccountDenied => "E016",
+            StakingError::TokenBlocked => "E017",
+            StakingError::Paused => "E018",
+            StakingError::ChangeNotFound => "E019",
+            StakingError::TimelockNotExpired => "E020",
+            StakingError::WrongToken => "E021",
+            StakingError::InsufficientRewardPool => "E022",
+            StakingError::InsufficientExcess => "E023",
+            StakingError::StakeUnstaking => "E024",
+            StakingError::ReceiptNotStakeable => "E025",
+            StakingError::StakePending => "E026",
+            StakingError::RewardsOutstanding => "E027",
+            StakingError::TokenNotHeld => "E028",
+            StakingError::BalanceUnavailable => "E029",
+            StakingError::BonusNotAccepted => "E030",
+        }
+    }
+
+    /// Aborts the current call with this error.
+    pub fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+const TGAS: u64 = 1_000_000_000_000;
+
+impl fmt::Display for StakingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.code())?;
+        match self {
+            StakingError::NoStakes => write!(f, "You didn't stake any token at all"),
+            StakingError::TokenNotStaked => write!(f, "The token is not staked by this account"),
+            StakingError::TokenAlreadyStaked => write!(f, "The token is already staked"),
+            StakingError::NotEnoughGas { required } => {
+                write!(
+                    f,
+                    "Not enough gas attached, attach at least {} TGas",
+                    required.0 / TGAS + 1
+                )
+            }
+            StakingError::Unauthorized => write!(f, "The caller is not allowed to do this"),
+            StakingError::InvalidConfig(reason) => write!(f, "Invalid config: {}", reason),
+            StakingError::CollectionNotAllowed => {
+                write!(f, "Tokens of this NFT contract can't be staked")
+            }
+            StakingError::TooManyStakes { max } => {
+                write!(
+                    f,
+                    "This account already has the maximum of {} tokens staked",
+                    max
+                )
+            }
+            StakingError::StakeLocked => {
+                write!(
+                    f,
+                    "The token can't be unstaked before the minimum lock duration"
+                )
+            }
+            StakingError::NothingToClaim => write!(f, "There are no rewards to claim"),
+            StakingError::EmptyBatch => write!(f, "Expected at least one token"),
+            StakingError::SoftStakeNotTransferable => {
+                write!(f, "Soft stakes can't be transferred")
+            }
+            StakingError::InvalidMsg => write!(f, "The msg is not a valid stake message"),
+            StakingError::InsufficientTreasury => {
+                write!(f, "The treasury doesn't hold the requested amount")
+            }
+            StakingError::PoolFull { max } => {
+                write!(
+                    f,
+                    "The contract already holds the maximum of {} staked tokens",
+                    max
+                )
+            }
+            StakingError::AccountDenied => write!(f, "This account can't stake or claim"),
+            StakingError::TokenBlocked => write!(f, "This token can't be staked"),
+            StakingError::Paused => write!(f, "The contract is paused"),
+            StakingError::ChangeNotFound => write!(f, "There is no pending change with this id"),
+            StakingError::TimelockNotExpired => {
+                write!(
+                    f,
+                    "The change can't be executed before its delay has passed"
+                )
+            }
+            StakingError::WrongToken => write!(f, "Only the reward token is accepted"),
+            StakingError::InsufficientRewardPool => {
+                write!(f, "The reward pool can't cover the payout")
+            }
+            StakingError::InsufficientExcess => {
+                write!(
+                    f,
+                    "The contract doesn't hold that much beyond the reward pool"
+                )
+            }
+            StakingError::StakeUnstaking => write!(f, "The token is being unstaked"),
+            StakingError::ReceiptNotStakeable => write!(f, "Stake receipts can't be staked"),
+            StakingError::StakePending => write!(f, "The token hasn't reached the contract yet"),
+            StakingError::RewardsOutstanding => {
+                write!(f, "Rewards are still owed in the current reward mode")
+            }
+            StakingError::TokenNotHeld => write!(f, "The contract doesn't hold this token"),
+            StakingError::BalanceUnavailable => write!(f, "Couldn't read the FT balance"),
+            StakingError::BonusNotAccepted => {
+                write!(f, "Bonus drops are only accepted in the FT reward mode")
+            }
+        }
+    }
+}
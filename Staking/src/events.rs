@@ -0,0 +1,124 @@
+//! Events logged by the staking contract, in the [NEP-297] format:
+//! `EVENT_JSON:{"standard":"nft_staking","version":"1.0.0","event":"...","data":[...]}`.
+//!
+//! [NEP-297]: https://nomicon.io/Standards/EventsFormat
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json;
+
+use crate::*;
+
+const STANDARD: &str = "nft_staking";
+const VERSION: &str = "1.0.0";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum StakingEvent<'a> {
+    StakeTransfer(&'a [StakeTransferData<'a>]),
+    Claim(&'a [ClaimData<'a>]),
+    AccountDenied(&'a [AccountData<'a>]),
+    AccountAllowed(&'a [AccountData<'a>]),
+    TokenBlocked(&'a [TokenData<'a>]),
+    TokenUnblocked(&'a [TokenData<'a>]),
+    RoleGranted(&'a [RoleData<'a>]),
+    RoleRevoked(&'a [RoleData<'a>]),
+    Paused(&'a [AccountData<'a>]),
+    Unpaused(&'a [AccountData<'a>]),
+    ChangeProposed(&'a [&'a PendingChange]),
+    ChangeExecuted(&'a [&'a PendingChange]),
+    ChangeCancelled(&'a [&'a PendingChange]),
+    BonusDistributed(&'a [BonusData]),
+    NftRescued(&'a [NftRescueData<'a>]),
+    FtRescued(&'a [FtRescueData<'a>]),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeTransferData<'a> {
+    pub old_owner_id: &'a AccountId,
+    pub new_owner_id: &'a AccountId,
+    pub nft_contract_id: &'a AccountId,
+    pub token_id: &'a TokenId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimData<'a> {
+    pub owner_id: &'a AccountId,
+    /// Claimed reward, boost included, before the fee.
+    pub amount: U128,
+    /// Claim fee, plus the instant claim penalty if any, kept in the treasury.
+    pub fee: U128,
+    /// Boost multiplier applied to the accrued reward, in basis points.
+    pub multiplier_bps: u32,
+    /// Whether the reward, less the fee, started vesting instead of being paid out.
+    pub vested: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountData<'a> {
+    pub account_id: &'a AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenData<'a> {
+    pub nft_contract_id: &'a AccountId,
+    pub token_id: &'a TokenId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleData<'a> {
+    pub account_id: &'a AccountId,
+    pub role: Role,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BonusData {
+    pub amount: U128,
+    /// Number of staked tokens the amount was split between.
+    pub total_stakes: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftRescueData<'a> {
+    pub nft_contract_id: &'a AccountId,
+    pub token_id: &'a TokenId,
+    pub receiver_id: &'a AccountId,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtRescueData<'a> {
+    pub ft_contract_id: &'a AccountId,
+    pub receiver_id: &'a AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a StakingEvent<'a>,
+}
+
+impl StakingEvent<'_> {
+    pub fn emit(&self) {
+        let log = EventLog {
+            standard: STANDARD,
+            version: VERSION,
+            event: self,
+        };
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            serde_json::to_string(&log).unwrap()
+        ));
+    }
+}
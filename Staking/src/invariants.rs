@@ -0,0 +1,88 @@
+use std::collections::BTreeSet;
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Outcome of `check_invariants`.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InvariantReport {
+    /// Whether every invariant holds.
+    pub ok: bool,
+    pub total_stakes: U64,
+    /// Sum of the stakes indexed under each staker.
+    pub account_stakes: U64,
+    /// Reward pool of the current reward mode.
+    pub reward_pool: U128,
+    /// Rewards accrued, bonus not split or settled yet, pending, settled and vesting rewards,
+    /// and the treasury.
+    pub outstanding_rewards: U128,
+    /// One line per invariant that doesn't hold.
+    pub mismatches: Vec<String>,
+}
+
+#[near_bindgen]
+impl CrossContract {
+    /// Checks that the per-account stake index matches the stakes, and that the reward pool
+    /// covers everything owed. Goes through every stake, so it is meant for off-chain audits.
+    pub fn check_invariants(&self) -> InvariantReport {
+        let now = env::block_timestamp();
+        let mut mismatches = Vec::new();
+        let mut owners = BTreeSet::new();
+        let mut outstanding = self.unpaid_rewards + self.treasury_balance + self.bonus_remainder;
+        for (contract_token_id, stake) in self.stakes.iter() {
+            let indexed = self
+                .stakes_per_owner
+                .get(&stake.owner_id)
+                .filter(|token_ids| token_ids.contains(&contract_token_id))
+                .is_some();
+            if !indexed {
+                mismatches.push(format!(
+                    "{} isn't indexed under its owner {}",
+                    contract_token_id, stake.owner_id
+                ));
+            }
+            outstanding += self.internal_accrued(&stake, now);
+            if stake.status != StakeStatus::Pending {
+                let settled_at = self.bonus_settled_at.get(&stake.owner_id).unwrap_or(0);
+                outstanding += self.bonus_per_stake - settled_at;
+            }
+            owners.insert(stake.owner_id);
+        }
+
+        let total_stakes = self.stakes.len();
+        let account_stakes: u64 = owners
+            .iter()
+            .filter_map(|owner_id| self.stakes_per_owner.get(owner_id))
+            .map(|token_ids| token_ids.len())
+            .sum();
+        if account_stakes != total_stakes {
+            mismatches.push(format!(
+                "{} stakes are indexed per account, {} in total",
+                account_stakes, total_stakes
+            ));
+        }
+
+        let reward_pool = match self.config.reward_mode {
+            RewardMode::Ft => self.ft_reward_pool,
+            RewardMode::Near => self.near_reward_pool,
+        };
+        if reward_pool < outstanding {
+            mismatches.push(format!(
+                "The reward pool holds {}, {} is outstanding",
+                reward_pool, outstanding
+            ));
+        }
+
+        InvariantReport {
+            ok: mismatches.is_empty(),
+            total_stakes: U64(total_stakes),
+            account_stakes: U64(account_stakes),
+            reward_pool: U128(reward_pool),
+            outstanding_rewards: U128(outstanding),
+            mismatches,
+        }
+    }
+}
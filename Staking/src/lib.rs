@@ -1,18 +1,187 @@
 use near_contract_standards::non_fungible_token::{Token, TokenId};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::*;
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
 use near_sdk::{
-    env, ext_contract, json_types::U128, log, near_bindgen, AccountId, PanicOnDefault, Promise,
-    PromiseOrValue,
+    assert_one_yocto, env, ext_contract, json_types::U128, near_bindgen, require, AccountId,
+    Balance, BorshStorageKey, Gas, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
+    ONE_YOCTO,
 };
 
+mod approval;
+mod bonus;
+mod claim;
+mod config;
+mod denylist;
+mod error;
+mod events;
+mod invariants;
+mod migrate;
+mod payout;
+mod receipt;
+mod receiver;
+mod referral;
+mod rescue;
+mod rewards;
+mod roles;
+mod sets;
+mod timelock;
+mod vesting;
+mod voting;
+
+pub use crate::config::{BoostTier, CapacityView, Config, RewardMode};
+pub use crate::error::StakingError;
+pub use crate::events::{
+    AccountData, BonusData, ClaimData, FtRescueData, NftRescueData, RoleData, StakeTransferData,
+    StakingEvent, TokenData,
+};
+pub use crate::invariants::InvariantReport;
+pub use crate::migrate::{CrossContractV1, StakeV1, VersionedCrossContract};
+pub use crate::receiver::StakeMsg;
+pub use crate::referral::{ReferralStatsView, ReferrerStats};
+pub use crate::rewards::RateCheckpoint;
+pub use crate::roles::Role;
+pub use crate::sets::{SetMembers, StakeSet};
+pub use crate::timelock::{ConfigChange, PendingChange};
+pub use crate::vesting::{VestingSchedule, VestingView};
+pub use crate::voting::Checkpoint;
+
+const TGAS: u64 = 1_000_000_000_000;
+/// Gas attached to every `nft_transfer` call on the NFT contract.
+const GAS_FOR_NFT_TRANSFER: Gas = Gas(15 * TGAS);
+/// Gas attached to every `nft_token` view call on the NFT contract.
+const GAS_FOR_NFT_TOKEN: Gas = Gas(5 * TGAS);
+/// Gas attached to every `ft_balance_of` view call on the FT contract.
+const GAS_FOR_FT_BALANCE_OF: Gas = Gas(5 * TGAS);
+/// Gas attached to every `ft_transfer` call on the FT contract.
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10 * TGAS);
+/// Gas attached to the callback resolving a single transfer.
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(10 * TGAS);
+/// Extra gas the batch stake callback needs for every token.
+const GAS_FOR_RESOLVE_BATCH_ITEM: Gas = Gas(2 * TGAS);
+/// Gas needed to pay out a reward and resolve the transfer.
+const GAS_FOR_PAYOUT: Gas = Gas(GAS_FOR_FT_TRANSFER.0 + GAS_FOR_RESOLVE_TRANSFER.0);
+/// Gas attached to the callback checking soft stakes and the boost, which then pays out the
+/// reward.
+const GAS_FOR_RESOLVE_CHECKED_CLAIM: Gas = Gas(GAS_FOR_RESOLVE_TRANSFER.0 + GAS_FOR_PAYOUT.0);
+/// Gas kept for the rest of the calling method after its promises are scheduled.
+const GAS_FOR_EXECUTION: Gas = Gas(10 * TGAS);
+
+const MEMO_STAKE: &str = "stake";
+const MEMO_UNSTAKE: &str = "unstake";
+const MEMO_CLAIM: &str = "claim";
+const MEMO_TREASURY: &str = "treasury";
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Stakes are keyed by NFT contract and token, as `"<nft_contract_id>:<token_id>"`.
+/// Account ids can't contain the delimiter, so the key is unambiguous.
+pub type ContractTokenId = String;
+const DELIMITER: char = ':';
+
+fn contract_token_id(nft_contract_id: &AccountId, token_id: &TokenId) -> ContractTokenId {
+    format!("{}{}{}", nft_contract_id, DELIMITER, token_id)
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct CrossContract {
+    owner_id: AccountId,
     nft_account: AccountId,
     ft_account: AccountId,
-    staked: UnorderedMap<AccountId, Vector<Stake>>,
-    unstaked: UnorderedMap<AccountId, Vector<u128>>,
+    config: Config,
+    /// Every staked token.
+    stakes: UnorderedMap<ContractTokenId, Stake>,
+    /// Tokens staked by each account.
+    stakes_per_owner: LookupMap<AccountId, UnorderedSet<ContractTokenId>>,
+    /// Rewards earned by each account that haven't been paid out yet, e.g. those of unstaked
+    /// tokens or of claims whose transfer failed.
+    pending_rewards: LookupMap<AccountId, u128>,
+    /// Fees and penalties kept from claims, withdrawable by the owner.
+    treasury_balance: u128,
+    /// Referrer of each referred account.
+    referrers: LookupMap<AccountId, AccountId>,
+    referrer_stats: LookupMap<AccountId, ReferrerStats>,
+    /// Claimed rewards still vesting, per account.
+    vesting: LookupMap<AccountId, Vec<VestingSchedule>>,
+    /// Voting power history of each account.
+    voting_checkpoints: LookupMap<AccountId, Vector<Checkpoint>>,
+    /// Voting power history of all stakers together.
+    total_voting_checkpoints: Vector<Checkpoint>,
+    /// Sets of tokens earning a bonus when staked together, by name.
+    sets: UnorderedMap<String, StakeSet>,
+    /// Sets each account has complete, as they were defined when they were last refreshed.
+    active_sets: LookupMap<AccountId, Vec<(String, StakeSet)>>,
+    /// Trait sets each staked token belongs to.
+    token_traits: LookupMap<ContractTokenId, Vec<String>>,
+    /// Rewards accrued by each account's stakes up to a change of its active sets, paid out with
+    /// its next claim.
+    settled_rewards: LookupMap<AccountId, u128>,
+    /// Accounts that can neither stake nor claim.
+    denied_accounts: UnorderedSet<AccountId>,
+    /// Tokens that can't be staked.
+    blocked_tokens: UnorderedSet<ContractTokenId>,
+    /// Roles granted to each account besides `owner_id`.
+    roles: LookupMap<AccountId, Vec<Role>>,
+    /// Whether staking and claiming are stopped.
+    paused: bool,
+    /// Timelocked config changes waiting to be executed, by id.
+    pending_changes: UnorderedMap<u64, PendingChange>,
+    next_change_id: u64,
+    /// Bonus drops earned by every staked token so far.
+    bonus_per_stake: u128,
+    /// Part of the bonus drops not split between the stakes yet.
+    bonus_remainder: u128,
+    /// `bonus_per_stake` as of the last time each account's bonus was settled.
+    bonus_settled_at: LookupMap<AccountId, u128>,
+    /// History of the reward rate.
+    rate_checkpoints: Vector<RateCheckpoint>,
+    /// NEAR deposited to pay out rewards in NEAR mode.
+    near_reward_pool: Balance,
+    /// Reward FT sent with `msg` `rewards` or as bonus drops, less what was paid out since.
+    ft_reward_pool: u128,
+    /// Pending, settled and vesting rewards of all accounts together.
+    unpaid_rewards: u128,
+    /// Stakes migrated from V1 whose token hasn't been seen in the contract's hands yet.
+    unverified_stakes: UnorderedSet<ContractTokenId>,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Stakes,
+    StakesPerOwner,
+    StakesPerOwnerInner { account_hash: Vec<u8> },
+    PendingRewards,
+    Vesting,
+    Referrers,
+    ReferrerStats,
+    VotingCheckpoints,
+    VotingCheckpointsInner { account_hash: Vec<u8> },
+    TotalVotingCheckpoints,
+    Sets,
+    ActiveSets,
+    TokenTraits,
+    SettledRewards,
+    DeniedAccounts,
+    BlockedTokens,
+    Roles,
+    PendingChanges,
+    BonusSettledAt,
+    RateCheckpoints,
+    UnverifiedStakes,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum StakeStatus {
+    Active,
+    /// The token is being sent back to its owner. The stake can't be transferred or unstaked
+    /// again until the transfer resolves.
+    Unstaking,
+    /// The token hasn't reached the contract yet. The stake carries no vote until its transfer
+    /// is confirmed, and is dropped if the transfer fails.
+    Pending,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Clone)]
@@ -20,6 +189,29 @@ pub struct Stake {
     timestamp: u64,
     staked_id: TokenId,
     owner_id: AccountId,
+    nft_contract_id: AccountId,
+    /// Time up to which rewards of this stake have been paid out.
+    claimed_at: u64,
+    /// Time the stake starts to earn, once past the warm-up period.
+    rewards_start_at: u64,
+    /// Set for soft stakes, where the token stays with its owner and the contract only holds
+    /// this approval.
+    approval_id: Option<u64>,
+    status: StakeStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeView {
+    pub owner_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub staked_at: U64,
+    pub claimed_at: U64,
+    /// Time the stake starts to earn, once past the warm-up period.
+    pub rewards_start_at: U64,
+    /// Set for soft stakes.
+    pub approval_id: Option<u64>,
 }
 
 pub trait From<T> {
@@ -51,29 +243,157 @@ impl From<&str> for near_sdk::AccountId {
 pub trait NFTCrossContract {
     fn nft_transfer(
         &self,
-        sender_id: AccountId,
         receiver_id: AccountId,
         token_id: TokenId,
         approval_id: Option<u64>,
         memo: Option<String>,
     ) -> (AccountId, Option<HashMap<AccountId, u64>>);
+    fn nft_token(&self, token_id: TokenId) -> Option<Token>;
 }
 
 #[ext_contract(ftext)]
 pub trait FTCrossContract {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+}
+
+#[ext_contract(ext_self)]
+pub trait SelfCallbacks {
+    fn resolve_stake_owners(
+        &mut self,
+        owner_id: AccountId,
+        nft_contract_id: AccountId,
+        token_ids: Vec<TokenId>,
+    ) -> PromiseOrValue<Vec<bool>>;
+    fn resolve_unstake(
+        &mut self,
+        owner_id: AccountId,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    ) -> bool;
+    fn resolve_stake_many(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_ids: Vec<TokenId>,
+        owned: Vec<bool>,
+    ) -> Vec<bool>;
+    fn resolve_claim(
+        &mut self,
+        owner_id: AccountId,
+        amount: U128,
+        fee: U128,
+        mode: RewardMode,
+    ) -> bool;
+    fn resolve_withdraw_treasury(&mut self, amount: U128, mode: RewardMode) -> bool;
+    fn resolve_rescue_nft(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        receiver_id: AccountId,
+    ) -> Promise;
+    fn resolve_nft_rescued(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        receiver_id: AccountId,
+    ) -> bool;
+    fn resolve_rescue_ft(
+        &mut self,
+        ft_contract_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Promise;
+    fn resolve_ft_rescued(
+        &mut self,
+        ft_contract_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> bool;
+    fn resolve_checked_claim(
+        &mut self,
+        owner_id: AccountId,
+        contract_token_ids: Vec<ContractTokenId>,
+        accrued: U128,
+        pending: U128,
+        instant: bool,
+        boosted: bool,
+    );
+    fn resolve_withdraw_vested(
+        &mut self,
+        owner_id: AccountId,
+        amount: U128,
+        mode: RewardMode,
+    ) -> bool;
+    fn resolve_token_traits(&mut self, contract_token_id: ContractTokenId);
+    fn resolve_migrated_stakes(&mut self, token_ids: Vec<TokenId>) -> Vec<bool>;
+}
+
+/// Panics with [`StakingError::NotEnoughGas`] unless the remaining prepaid gas covers `required`
+/// plus what the calling method itself still needs.
+fn assert_enough_gas(required: Gas) {
+    let required = Gas(required.0 + GAS_FOR_EXECUTION.0);
+    if env::prepaid_gas().0 < env::used_gas().0 + required.0 {
+        StakingError::NotEnoughGas {
+            required: Gas(env::used_gas().0 + required.0),
+        }
+        .panic();
+    }
+}
+
+fn is_promise_success() -> bool {
+    require!(
+        env::promise_results_count() == 1,
+        "Contract expected a result on the callback"
+    );
+    matches!(env::promise_result(0), PromiseResult::Successful(_))
 }
 
 #[near_bindgen]
 impl CrossContract {
     // Default Constructor
     #[init]
-    pub fn new(ft_account: AccountId, nft_account: AccountId) -> Self {
+    pub fn new(
+        owner_id: AccountId,
+        ft_account: AccountId,
+        nft_account: AccountId,
+        config: Option<Config>,
+    ) -> Self {
+        let config = config.unwrap_or_default();
+        config.assert_valid();
+        let rate_checkpoints = Self::initial_rate_checkpoints(config.reward_rate.0);
+        migrate::write_state_version();
         Self {
+            owner_id,
             ft_account,
             nft_account,
-            staked: UnorderedMap::new(b"staked".to_vec()),
-            unstaked: UnorderedMap::new(b"unstaked".to_vec()),
+            config,
+            stakes: UnorderedMap::new(StorageKey::Stakes),
+            stakes_per_owner: LookupMap::new(StorageKey::StakesPerOwner),
+            pending_rewards: LookupMap::new(StorageKey::PendingRewards),
+            treasury_balance: 0,
+            referrers: LookupMap::new(StorageKey::Referrers),
+            referrer_stats: LookupMap::new(StorageKey::ReferrerStats),
+            vesting: LookupMap::new(StorageKey::Vesting),
+            voting_checkpoints: LookupMap::new(StorageKey::VotingCheckpoints),
+            total_voting_checkpoints: Vector::new(StorageKey::TotalVotingCheckpoints),
+            sets: UnorderedMap::new(StorageKey::Sets),
+            active_sets: LookupMap::new(StorageKey::ActiveSets),
+            token_traits: LookupMap::new(StorageKey::TokenTraits),
+            settled_rewards: LookupMap::new(StorageKey::SettledRewards),
+            denied_accounts: UnorderedSet::new(StorageKey::DeniedAccounts),
+            blocked_tokens: UnorderedSet::new(StorageKey::BlockedTokens),
+            roles: LookupMap::new(StorageKey::Roles),
+            paused: false,
+            pending_changes: UnorderedMap::new(StorageKey::PendingChanges),
+            next_change_id: 0,
+            bonus_per_stake: 0,
+            bonus_remainder: 0,
+            bonus_settled_at: LookupMap::new(StorageKey::BonusSettledAt),
+            rate_checkpoints,
+            near_reward_pool: 0,
+            ft_reward_pool: 0,
+            unpaid_rewards: 0,
+            unverified_stakes: UnorderedSet::new(StorageKey::UnverifiedStakes),
         }
     }
 
@@ -87,142 +407,2869 @@ impl CrossContract {
     //         );
     // }
 
+    /// Stakes `token_id` of `nft_contract_id` (`nft_account` if omitted), which the caller must
+    /// own and have approved the contract for. Ownership is checked with `nft_token` before the
+    /// token is transferred. Requires exactly 1 yoctoNEAR; the contract covers the 1 yoctoNEAR
+    /// that `nft_transfer` needs.
+    #[payable]
     #[result_serializer(borsh)]
-    pub fn stake(&mut self, token_id: TokenId) /*  -> PromiseOrValue<TokenId>  */
+    pub fn stake(&mut self, token_id: TokenId, nft_contract_id: Option<AccountId>)
+    /*  -> PromiseOrValue<TokenId>  */
     {
         //nftext::nft_transfer_call(&self, token_id, "Stake NFT");
+        assert_one_yocto();
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        self.internal_stake(nft_contract_id, vec![token_id]);
+        //nftext::nft_transfer_call(&mut self, self.nft_account, "transfer nft");
+    }
+
+    /// Stakes all of `token_ids` of `nft_contract_id` (`nft_account` if omitted) at once, like
+    /// `stake`. The checks and transfers run in parallel and a single callback resolves them,
+    /// returning whether each token was staked, in the order given. Requires exactly 1
+    /// yoctoNEAR; the contract covers the 1 yoctoNEAR that each `nft_transfer` needs.
+    #[payable]
+    pub fn stake_many(
+        &mut self,
+        token_ids: Vec<TokenId>,
+        nft_contract_id: Option<AccountId>,
+    ) -> Promise {
+        assert_one_yocto();
+        if token_ids.is_empty() {
+            StakingError::EmptyBatch.panic();
+        }
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        self.internal_stake(nft_contract_id, token_ids)
+    }
+
+    /// Returns all of the caller's staked tokens, once they are all past the minimum lock
+    /// duration, have reached the contract and none of them is being unstaked already. Soft
+    /// stakes are dropped right away, along with their unclaimed rewards, which can't be verified
+    /// without a claim. Accounts with more tokens than one call has gas for can unstake them in
+    /// parts with `unstake_many`. Requires exactly 1 yoctoNEAR; the contract covers the
+    /// 1 yoctoNEAR that each `nft_transfer` needs.
+    #[payable]
+    #[result_serializer(borsh)]
+    pub fn unstake(&mut self) {
+        assert_one_yocto();
         let caller = env::predecessor_account_id();
-        let current_timestamp = env::block_timestamp();
-        //let mut _staked = self.staked.get(&caller).unwrap().clone();
-        match self.staked.get(&caller) {
-            Some(mut _staked) => {
-                _staked.push(&Stake {
-                    timestamp: current_timestamp,
-                    staked_id: token_id.clone(),
-                    owner_id: caller.clone(),
-                });
+        let stakes = self
+            .internal_stakes_of(&caller)
+            .iter()
+            .map(|contract_token_id| self.stakes.get(&contract_token_id).unwrap())
+            .collect();
+        self.internal_unstake(caller, stakes);
+    }
+
+    /// Returns `token_ids` of `nft_contract_id` (`nft_account` if omitted) like `unstake`,
+    /// leaving the caller's other stakes in place. Each token can only be listed once, as with
+    /// `stake_many`.
+    #[payable]
+    pub fn unstake_many(&mut self, token_ids: Vec<TokenId>, nft_contract_id: Option<AccountId>) {
+        assert_one_yocto();
+        if token_ids.is_empty() {
+            StakingError::EmptyBatch.panic();
+        }
+        let mut unique_ids: Vec<&TokenId> = token_ids.iter().collect();
+        unique_ids.sort();
+        unique_ids.dedup();
+        if unique_ids.len() != token_ids.len() {
+            StakingError::TokenAlreadyStaked.panic();
+        }
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        let caller = env::predecessor_account_id();
+        let stakes = token_ids
+            .iter()
+            .map(|token_id| {
+                self.stakes
+                    .get(&contract_token_id(&nft_contract_id, token_id))
+                    .filter(|stake| stake.owner_id == caller)
+                    .unwrap_or_else(|| StakingError::TokenNotStaked.panic())
+            })
+            .collect();
+        self.internal_unstake(caller, stakes);
+    }
+
+    /// Claims the caller's rewards, including pending ones, less the claim fee. Soft stakes are
+    /// first checked with `nft_token` and only earn if the caller still owns the token and the
+    /// approval is still in place. With boost tiers configured, the caller's balance of the
+    /// reward FT is read with `ft_balance_of` and the rewards accrued by its stakes are
+    /// multiplied by the tier reached. When vesting is configured the rewards start vesting,
+    /// unless `instant` is set, in which case they are paid out right away less the instant
+    /// claim penalty. Requires exactly 1 yoctoNEAR, which is forwarded to `ft_transfer`.
+    #[payable]
+    #[result_serializer(borsh)]
+    pub fn claim(&mut self, instant: Option<bool>) {
+        assert_one_yocto();
+        self.assert_not_paused();
+        let caller = env::predecessor_account_id();
+        self.assert_not_denied(&caller);
+        let now = env::block_timestamp();
+        self.internal_refresh_active_sets(&caller);
+        self.internal_settle_bonus(&caller);
+        // Rewards for all stakes are paid out in a single transfer.
+        let pending = self.pending_rewards.remove(&caller).unwrap_or(0);
+        let settled = self.settled_rewards.remove(&caller).unwrap_or(0);
+        self.unpaid_rewards -= pending + settled;
+        let contract_token_ids = match self.stakes_per_owner.get(&caller) {
+            Some(token_ids) => token_ids.to_vec(),
+            None if pending + settled > 0 => Vec::new(),
+            None => StakingError::NoStakes.panic(),
+        };
+        let boosted = self.is_boost_enabled(contract_token_ids.len());
+        let mut accrued = settled;
+        let mut soft_stakes = Vec::new();
+        for contract_token_id in contract_token_ids {
+            let mut stake = self.stakes.get(&contract_token_id).unwrap();
+            if stake.approval_id.is_some() {
+                soft_stakes.push(stake);
+                continue;
             }
-            None => {
-                let mut new_vec: Vector<Stake> = Vector::new(b"new_vec".to_vec());
-                new_vec.push(&Stake {
-                    timestamp: current_timestamp,
-                    staked_id: token_id.clone(),
-                    owner_id: caller.clone(),
-                });
-                self.staked.insert(&caller, &new_vec);
+            // Its rewards are kept for the next claim once the token is back with its owner, or
+            // once it has reached the contract.
+            if stake.status != StakeStatus::Active {
+                continue;
             }
+            accrued += self.internal_accrued(&stake, now);
+            stake.claimed_at = now;
+            self.stakes.insert(&contract_token_id, &stake);
         }
-        // ------------------------------------------------------
-
-        match self.unstaked.get(&caller) {
-            Some(mut _unstaked) => {
-                _unstaked.push(&0);
+        let instant = instant.unwrap_or(false);
+        if soft_stakes.is_empty() && !boosted {
+            assert_enough_gas(GAS_FOR_PAYOUT);
+            self.internal_pay_referral(&caller, accrued);
+            let reward = pending + accrued;
+            if reward == self.internal_claim_fee(reward) {
+                StakingError::NothingToClaim.panic();
             }
-            None => {
-                let new_vec: Vector<u128> = Vector::new(b"new_vec".to_vec());
-                self.unstaked.insert(&caller, &new_vec);
+            if !self.internal_distribute_reward(caller, reward, instant, config::NO_BOOST_BPS) {
+                StakingError::InsufficientRewardPool.panic();
             }
+        } else {
+            assert_enough_gas(self.gas_for_checked_claim(soft_stakes.len(), boosted));
+            // Kept pending until the checks resolve, so that a failed callback can't lose them.
+            self.internal_add_pending_reward(&caller, pending + accrued);
+            self.internal_checked_claim(caller, soft_stakes, accrued, pending, instant, boosted);
         }
-        nftext::nft_transfer(
-            caller.clone(),
-            env::current_account_id(),
-            token_id,
-            Some(1u64),
-            Some(String::from("memo")),
-            self.nft_account.clone(), // contract account id
-            1,                        // yocto NEAR to attach
-            near_sdk::Gas(20000),     // gas to attach
-        );
-        //nftext::nft_transfer_call(&mut self, self.nft_account, "transfer nft");
     }
 
-    #[result_serializer(borsh)]
-    pub fn unstake(&mut self) {
-        let owner = env::current_account_id();
+    /// Moves the caller's stake of `token_id` of `nft_contract_id` (`nft_account` if omitted) to
+    /// `receiver_id`, together with its unclaimed rewards, lock and receipt. Soft stakes can't be
+    /// transferred, as the token itself stays with its owner, and neither can stakes whose token
+    /// hasn't reached the contract yet. Neither the caller nor
+    /// `receiver_id` may be on the denylist. Requires exactly 1 yoctoNEAR.
+    #[payable]
+    pub fn transfer_stake(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        nft_contract_id: Option<AccountId>,
+    ) {
+        assert_one_yocto();
+        self.assert_not_paused();
+        assert_enough_gas(Gas(self.gas_for_receipts(1)));
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
         let caller = env::predecessor_account_id();
-        match self.staked.get(&caller) {
-            Some(mut _staked) => {
-                _staked.iter().for_each(|ele| {
-                    /* nftext::nft_transfer_call(
-                        owner,
-                        caller,
-                        ele.staked_id,
-                        String::from("unstake"),
-                        String::from("unstake"),
-                    ); */
-                    if ele.owner_id == caller {
-                        nftext::nft_transfer(
-                            owner.clone(),
-                            caller.clone(),
-                            ele.staked_id,
-                            Some(1u64),
-                            Some(String::from("memo")),
-                            self.nft_account.clone(), // contract account id
-                            0,                        // yocto NEAR to attach
-                            env::prepaid_gas(),       // gas to attach
-                        );
+        self.assert_not_denied(&caller);
+        let contract_token_id = contract_token_id(&nft_contract_id, &token_id);
+        let mut stake = self
+            .internal_remove_stake(&caller, &contract_token_id)
+            .unwrap_or_else(|| StakingError::TokenNotStaked.panic());
+        if stake.approval_id.is_some() {
+            StakingError::SoftStakeNotTransferable.panic();
+        }
+        match stake.status {
+            StakeStatus::Unstaking => StakingError::StakeUnstaking.panic(),
+            StakeStatus::Pending => StakingError::StakePending.panic(),
+            StakeStatus::Active => {}
+        }
+        self.assert_not_denied(&receiver_id);
+        self.assert_can_stake_more(&receiver_id);
+        self.internal_transfer_receipt(&stake, &receiver_id);
+        stake.owner_id = receiver_id.clone();
+        self.internal_insert_stake(stake);
+        StakingEvent::StakeTransfer(&[StakeTransferData {
+            old_owner_id: &caller,
+            new_owner_id: &receiver_id,
+            nft_contract_id: &nft_contract_id,
+            token_id: &token_id,
+        }])
+        .emit();
+    }
+
+    pub fn get_stake(
+        &self,
+        token_id: TokenId,
+        nft_contract_id: Option<AccountId>,
+    ) -> Option<StakeView> {
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        self.stakes
+            .get(&contract_token_id(&nft_contract_id, &token_id))
+            .map(|stake| StakeView {
+                owner_id: stake.owner_id,
+                nft_contract_id: stake.nft_contract_id,
+                token_id: stake.staked_id,
+                staked_at: U64(stake.timestamp),
+                claimed_at: U64(stake.claimed_at),
+                rewards_start_at: U64(stake.rewards_start_at),
+                approval_id: stake.approval_id,
+            })
+    }
+
+    /// Reward accrued so far by `token_id` of `nft_contract_id` (`nft_account` if omitted),
+    /// nothing while it is still warming up.
+    #[result_serializer(borsh)]
+    pub fn get_claimable(&self, token_id: TokenId, nft_contract_id: Option<AccountId>) -> u128 {
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        match self
+            .stakes
+            .get(&contract_token_id(&nft_contract_id, &token_id))
+        {
+            Some(stake) => self.internal_accrued(&stake, env::block_timestamp()),
+            None => StakingError::TokenNotStaked.panic(),
+        }
+    }
+
+    /// Sends `amount` of the fees collected on claims (all of them if omitted) to `receiver_id`.
+    /// Only owners can call this. Requires exactly 1 yoctoNEAR, which is forwarded to
+    /// `ft_transfer`.
+    #[payable]
+    pub fn withdraw_treasury(&mut self, receiver_id: AccountId, amount: Option<U128>) -> Promise {
+        assert_one_yocto();
+        self.assert_role(Role::Owner);
+        assert_enough_gas(GAS_FOR_PAYOUT);
+        let amount = amount.map_or(self.treasury_balance, |amount| amount.0);
+        if amount == 0 || amount > self.treasury_balance {
+            StakingError::InsufficientTreasury.panic();
+        }
+        if !self.can_pay(amount) {
+            StakingError::InsufficientRewardPool.panic();
+        }
+        self.treasury_balance -= amount;
+        self.internal_pay(receiver_id, amount, MEMO_TREASURY).then(
+            ext_self::resolve_withdraw_treasury(
+                amount.into(),
+                self.config.reward_mode,
+                env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ),
+        )
+    }
+
+    pub fn get_treasury_balance(&self) -> U128 {
+        self.treasury_balance.into()
+    }
+
+    /// Transfers the tokens `owner_id` still owns and has approved the contract for, with the
+    /// approval id read, and rolls back the pending stakes of the others, whoever holds them.
+    #[private]
+    pub fn resolve_stake_owners(
+        &mut self,
+        owner_id: AccountId,
+        nft_contract_id: AccountId,
+        token_ids: Vec<TokenId>,
+    ) -> PromiseOrValue<Vec<bool>> {
+        require!(
+            env::promise_results_count() == token_ids.len() as u64,
+            "Contract expected a result for every token"
+        );
+        let approval_ids: Vec<Option<u64>> = token_ids
+            .iter()
+            .enumerate()
+            .map(|(index, token_id)| {
+                let contract_token_id = contract_token_id(&nft_contract_id, token_id);
+                let pending = self
+                    .stakes
+                    .get(&contract_token_id)
+                    .filter(|stake| stake.status == StakeStatus::Pending);
+                let staked = pending
+                    .as_ref()
+                    .filter(|stake| stake.owner_id == owner_id)
+                    .is_some();
+                let approval_id = match env::promise_result(index as u64) {
+                    PromiseResult::Successful(value) => {
+                        serde_json::from_slice::<Option<Token>>(&value)
+                            .ok()
+                            .flatten()
+                            .filter(|token| token.owner_id == owner_id)
+                            .and_then(|token| token.approved_account_ids)
+                            .and_then(|approvals| {
+                                approvals.get(&env::current_account_id()).copied()
+                            })
                     }
-                });
+                    _ => None,
+                };
+                let approval_id = approval_id.filter(|_| staked);
+                if pending.is_some() && approval_id.is_none() {
+                    self.internal_resolve_stake(&contract_token_id, false);
+                }
+                approval_id
+            })
+            .collect();
+        let owned: Vec<bool> = approval_ids.iter().map(Option::is_some).collect();
+        let gas_for_resolve =
+            self.gas_for_resolve_stake_many(&nft_contract_id, token_ids.len() as u64);
+        let transfers = token_ids
+            .iter()
+            .zip(approval_ids)
+            .filter_map(|(token_id, approval_id)| {
+                Some(nftext::nft_transfer(
+                    env::current_account_id(),
+                    token_id.clone(),
+                    Some(approval_id?),
+                    Some(MEMO_STAKE.to_string()),
+                    nft_contract_id.clone(), // contract account id
+                    ONE_YOCTO,               // yocto NEAR to attach
+                    GAS_FOR_NFT_TRANSFER,    // gas to attach
+                ))
+            })
+            .reduce(Promise::and);
+        match transfers {
+            Some(transfers) => transfers
+                .then(ext_self::resolve_stake_many(
+                    nft_contract_id,
+                    token_ids,
+                    owned,
+                    env::current_account_id(),
+                    0,
+                    Gas(gas_for_resolve),
+                ))
+                .into(),
+            None => PromiseOrValue::Value(owned),
+        }
+    }
+
+    /// Rolls back the stakes of the tokens that could not be transferred to the contract and
+    /// mints receipts for the others. Only the `owned` tokens were transferred.
+    #[private]
+    pub fn resolve_stake_many(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_ids: Vec<TokenId>,
+        owned: Vec<bool>,
+    ) -> Vec<bool> {
+        require!(
+            env::promise_results_count() == owned.iter().filter(|owned| **owned).count() as u64,
+            "Contract expected a result for every transfer"
+        );
+        let mut result_index = 0;
+        token_ids
+            .iter()
+            .zip(owned)
+            .map(|(token_id, owned)| {
+                if !owned {
+                    return false;
+                }
+                let transferred = matches!(
+                    env::promise_result(result_index),
+                    PromiseResult::Successful(_)
+                );
+                result_index += 1;
+                self.internal_resolve_stake(
+                    &contract_token_id(&nft_contract_id, token_id),
+                    transferred,
+                );
+                transferred
+            })
+            .collect()
+    }
+
+    /// Forgets the stake once the NFT has been returned to its owner, keeping the rewards it
+    /// earned for the next claim, and burns its receipt. The stake is kept if the transfer
+    /// failed.
+    #[private]
+    pub fn resolve_unstake(
+        &mut self,
+        owner_id: AccountId,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+    ) -> bool {
+        let transferred = is_promise_success();
+        if transferred {
+            let contract_token_id = contract_token_id(&nft_contract_id, &token_id);
+            if let Some(stake) = self.internal_remove_stake(&owner_id, &contract_token_id) {
+                let reward = self.internal_accrued(&stake, env::block_timestamp());
+                self.internal_add_pending_reward(&owner_id, reward);
+                self.internal_burn_receipt(&stake);
             }
-            None => {
-                log!("You didn't stake any token at all.");
+        } else {
+            let contract_token_id = contract_token_id(&nft_contract_id, &token_id);
+            if let Some(mut stake) = self.stakes.get(&contract_token_id) {
+                stake.status = StakeStatus::Active;
+                self.stakes.insert(&contract_token_id, &stake);
             }
         }
+        transferred
     }
 
-    #[result_serializer(borsh)]
-    pub fn claim(&self, token_id: TokenId) {
+    /// Adds the `fee` kept from the claimed reward to the treasury once it was paid out, or gives
+    /// the whole reward back if the transfer failed.
+    #[private]
+    pub fn resolve_claim(
+        &mut self,
+        owner_id: AccountId,
+        amount: U128,
+        fee: U128,
+        mode: RewardMode,
+    ) -> bool {
+        let transferred = is_promise_success();
+        if transferred {
+            self.treasury_balance += fee.0;
+        } else {
+            self.internal_refund_payout(mode, amount.0 - fee.0);
+            self.internal_add_pending_reward(&owner_id, amount.0);
+        }
+        transferred
+    }
+
+    /// Returns the amount to the treasury if the FT transfer failed.
+    #[private]
+    pub fn resolve_withdraw_treasury(&mut self, amount: U128, mode: RewardMode) -> bool {
+        let transferred = is_promise_success();
+        if !transferred {
+            self.internal_refund_payout(mode, amount.0);
+            self.treasury_balance += amount.0;
+        }
+        transferred
+    }
+}
+
+impl CrossContract {
+    /// Tokens staked by `account_id`, panicking with [`StakingError::NoStakes`] if none.
+    fn internal_stakes_of(&self, account_id: &AccountId) -> UnorderedSet<ContractTokenId> {
+        self.stakes_per_owner
+            .get(account_id)
+            .filter(|token_ids| !token_ids.is_empty())
+            .unwrap_or_else(|| StakingError::NoStakes.panic())
+    }
+
+    /// Reward `stake` has accrued since it was last claimed, or since its warm-up ended, up to
+    /// `now`, at the rates in effect meanwhile, set bonus included.
+    fn internal_accrued(&self, stake: &Stake, now: u64) -> u128 {
+        let since = stake.claimed_at.max(stake.rewards_start_at);
+        if now <= since {
+            return 0;
+        }
+        let accrued = self.reward_per_stake_at(now) - self.reward_per_stake_at(since);
+        let multiplier_bps = config::NO_BOOST_BPS + self.internal_set_bonus_bps(stake);
+        accrued * multiplier_bps as u128 / config::NO_BOOST_BPS as u128
+    }
+
+    /// Stakes `token_ids` for the caller, to be checked with `nft_token` and then transferred
+    /// by `resolve_stake_owners`.
+    fn internal_stake(&mut self, nft_contract_id: AccountId, token_ids: Vec<TokenId>) -> Promise {
+        let count = token_ids.len() as u64;
+        let gas_for_owners = self.gas_for_resolve_stake_owners(&nft_contract_id, count);
+        assert_enough_gas(Gas(count * GAS_FOR_NFT_TOKEN.0 + gas_for_owners));
         let caller = env::predecessor_account_id();
-        match self.staked.get(&caller) {
-            Some(mut _staked) => {
-                _staked.iter().for_each(|ele| {
-                    /* nftext::nft_transfer_call(
-                        owner,
-                        caller,
-                        ele.staked_id,
-                        String::from("unstake"),
-                        String::from("unstake"),
-                    ); */
-                    if ele.owner_id == caller {
-                        ftext::ft_transfer(
-                            env::predecessor_account_id(),
-                            1_000_000_000_000_000_000u128.into(),
-                            Some("claim".into()),
-                            self.nft_account.clone(), // contract account id
-                            1,                        // yocto NEAR to attach
-                            env::prepaid_gas(),       // gas to attach
-                        );
-                    }
-                });
+        for token_id in token_ids.iter() {
+            self.internal_add_stake(&caller, &nft_contract_id, token_id, None);
+        }
+        token_ids
+            .iter()
+            .map(|token_id| {
+                nftext::nft_token(
+                    token_id.clone(),
+                    nft_contract_id.clone(),
+                    0,
+                    GAS_FOR_NFT_TOKEN,
+                )
+            })
+            .reduce(Promise::and)
+            .unwrap()
+            .then(ext_self::resolve_stake_owners(
+                caller,
+                nft_contract_id,
+                token_ids,
+                env::current_account_id(),
+                0,
+                Gas(gas_for_owners),
+            ))
+    }
+
+    /// Gas the ownership check of `count` tokens needs to transfer them, or roll them back, and
+    /// resolve them.
+    fn gas_for_resolve_stake_owners(&self, nft_contract_id: &AccountId, count: u64) -> u64 {
+        GAS_FOR_RESOLVE_TRANSFER.0
+            + count
+                * (GAS_FOR_NFT_TRANSFER.0
+                    + GAS_FOR_RESOLVE_BATCH_ITEM.0
+                    + self.gas_for_set_refresh())
+            + self.gas_for_resolve_stake_many(nft_contract_id, count)
+    }
+
+    /// Gas `resolve_stake_many` needs for `count` tokens, each of which refreshes the active sets
+    /// of its owner.
+    fn gas_for_resolve_stake_many(&self, nft_contract_id: &AccountId, count: u64) -> u64 {
+        GAS_FOR_RESOLVE_TRANSFER.0
+            + count * (GAS_FOR_RESOLVE_BATCH_ITEM.0 + self.gas_for_set_refresh())
+            + self.gas_for_receipts(count)
+            + self.gas_for_token_traits(nft_contract_id, count)
+    }
+
+    fn internal_claim_fee(&self, reward: u128) -> u128 {
+        reward * self.config.claim_fee_bps as u128 / config::MAX_BPS as u128
+    }
+
+    /// Vests `reward`, less the claim fee, for `account_id`, or sends it right away if vesting
+    /// is off or `instant` is set. An instant claim while vesting is on also pays the penalty.
+    /// Fee and penalty go to the treasury, once the payout succeeded if it isn't vested. The
+    /// whole reward goes back to the pending rewards if the transfer fails. Logs a `claim` event
+    /// with the boost `multiplier_bps` applied. Returns false, keeping the whole reward pending,
+    /// if the NEAR pool can't cover the payout.
+    fn internal_distribute_reward(
+        &mut self,
+        account_id: AccountId,
+        reward: u128,
+        instant: bool,
+        multiplier_bps: u32,
+    ) -> bool {
+        let mut fee = self.internal_claim_fee(reward);
+        let vested = self.is_vesting_enabled() && !instant;
+        if self.is_vesting_enabled() && instant {
+            fee += (reward - fee) * self.config.instant_claim_penalty_bps as u128
+                / config::MAX_BPS as u128;
+        }
+        if !vested && !self.can_pay(reward - fee) {
+            self.internal_add_pending_reward(&account_id, reward);
+            return false;
+        }
+        StakingEvent::Claim(&[ClaimData {
+            owner_id: &account_id,
+            amount: reward.into(),
+            fee: fee.into(),
+            multiplier_bps,
+            vested,
+        }])
+        .emit();
+        if vested {
+            self.treasury_balance += fee;
+            let (cliff, duration) = (self.config.vesting_cliff.0, self.config.vesting_duration.0);
+            self.internal_add_vesting(&account_id, reward - fee, cliff, duration);
+            return true;
+        }
+        self.internal_pay(account_id.clone(), reward - fee, MEMO_CLAIM)
+            .then(ext_self::resolve_claim(
+                account_id,
+                reward.into(),
+                fee.into(),
+                self.config.reward_mode,
+                env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ));
+        true
+    }
+
+    pub(crate) fn internal_add_pending_reward(&mut self, account_id: &AccountId, amount: u128) {
+        if amount > 0 {
+            let pending = self.pending_rewards.get(account_id).unwrap_or(0);
+            self.pending_rewards.insert(account_id, &(pending + amount));
+            self.unpaid_rewards += amount;
+        }
+    }
+
+    /// Takes up to `amount` out of the pending rewards of `account_id` and returns what it took.
+    pub(crate) fn internal_take_pending_reward(
+        &mut self,
+        account_id: &AccountId,
+        amount: u128,
+    ) -> u128 {
+        let pending = self.pending_rewards.get(account_id).unwrap_or(0);
+        let taken = pending.min(amount);
+        if taken == pending {
+            self.pending_rewards.remove(account_id);
+        } else {
+            self.pending_rewards.insert(account_id, &(pending - taken));
+        }
+        self.unpaid_rewards -= taken;
+        taken
+    }
+
+    /// Confirms a stake whose token reached the contract, which gives its owner a vote, mints its
+    /// receipt and reads its traits, or drops it from whoever holds it by now, as it may have
+    /// been transferred in the meantime.
+    pub(crate) fn internal_resolve_stake(
+        &mut self,
+        contract_token_id: &ContractTokenId,
+        transferred: bool,
+    ) {
+        if transferred {
+            if let Some(mut stake) = self.stakes.get(contract_token_id) {
+                self.internal_settle_bonus(&stake.owner_id);
+                stake.status = StakeStatus::Active;
+                self.stakes.insert(contract_token_id, &stake);
+                self.internal_checkpoint_votes(&stake.owner_id, true);
+                self.internal_refresh_active_sets(&stake.owner_id);
+                self.internal_mint_receipt(&stake);
+                self.internal_fetch_token_traits(&stake);
             }
-            None => {
-                log!("You are not valid claimer.");
+        } else if let Some(stake) = self.stakes.get(contract_token_id) {
+            self.internal_remove_stake(&stake.owner_id, contract_token_id);
+        }
+    }
+
+    /// Drops the soft ones among `stakes` and sends the tokens of the others back to `owner_id`,
+    /// once they are all past the minimum lock duration. The others are marked as unstaking
+    /// until their transfer resolves.
+    fn internal_unstake(&mut self, owner_id: AccountId, stakes: Vec<Stake>) {
+        let (soft_stakes, stakes): (Vec<Stake>, Vec<Stake>) = stakes
+            .into_iter()
+            .partition(|stake| stake.approval_id.is_some());
+        let now = env::block_timestamp();
+        if soft_stakes
+            .iter()
+            .chain(&stakes)
+            .any(|stake| now < stake.timestamp + self.config.min_lock_duration.0)
+        {
+            StakingError::StakeLocked.panic();
+        }
+        if stakes
+            .iter()
+            .any(|stake| stake.status == StakeStatus::Unstaking)
+        {
+            StakingError::StakeUnstaking.panic();
+        }
+        if stakes
+            .iter()
+            .any(|stake| stake.status == StakeStatus::Pending)
+        {
+            StakingError::StakePending.panic();
+        }
+        for stake in soft_stakes {
+            self.internal_remove_stake(
+                &owner_id,
+                &contract_token_id(&stake.nft_contract_id, &stake.staked_id),
+            );
+        }
+        let gas_for_resolve =
+            GAS_FOR_RESOLVE_TRANSFER.0 + self.gas_for_receipts(1) + self.gas_for_set_refresh();
+        assert_enough_gas(Gas(
+            stakes.len() as u64 * (GAS_FOR_NFT_TRANSFER.0 + gas_for_resolve)
+        ));
+        for mut stake in stakes {
+            stake.status = StakeStatus::Unstaking;
+            self.stakes.insert(
+                &contract_token_id(&stake.nft_contract_id, &stake.staked_id),
+                &stake,
+            );
+            nftext::nft_transfer(
+                owner_id.clone(),
+                stake.staked_id.clone(),
+                None,
+                Some(MEMO_UNSTAKE.to_string()),
+                stake.nft_contract_id.clone(), // contract account id
+                ONE_YOCTO,                     // yocto NEAR to attach
+                GAS_FOR_NFT_TRANSFER,          // gas to attach
+            )
+            .then(ext_self::resolve_unstake(
+                owner_id.clone(),
+                stake.nft_contract_id,
+                stake.staked_id,
+                env::current_account_id(),
+                0,
+                Gas(gas_for_resolve),
+            ));
+        }
+    }
+
+    /// Panics with [`StakingError::TooManyStakes`] if `account_id` can't take another stake, or
+    /// with [`StakingError::PoolFull`] if the contract can't.
+    fn assert_can_stake_more(&self, account_id: &AccountId) {
+        let max = self.config.max_stakes_per_account;
+        let staked_count = self
+            .stakes_per_owner
+            .get(account_id)
+            .map_or(0, |ids| ids.len());
+        if staked_count >= max as u64 {
+            StakingError::TooManyStakes { max }.panic();
+        }
+        if let Some(max) = self.config.max_total_stakes {
+            if self.stakes.len() >= max.0 {
+                StakingError::PoolFull { max: max.0 }.panic();
             }
         }
     }
 
-    #[result_serializer(borsh)]
-    pub fn get_claimable(&self, token_id: TokenId) -> u128 {
-        let caller = env::predecessor_account_id();
-        let current_timestamp = env::block_timestamp();
-        let mut staked_timestamp = 0;
-        match self.staked.get(&caller) {
-            Some(mut _staked) => {
-                _staked.iter().for_each(|ele| {
-                    if ele.staked_id == token_id {
-                        staked_timestamp = ele.timestamp;
-                    }
+    fn internal_add_stake(
+        &mut self,
+        owner_id: &AccountId,
+        nft_contract_id: &AccountId,
+        token_id: &TokenId,
+        approval_id: Option<u64>,
+    ) {
+        if *nft_contract_id != self.nft_account
+            && !self.config.allowed_collections.contains(nft_contract_id)
+        {
+            StakingError::CollectionNotAllowed.panic();
+        }
+        if self.config.receipt_contract_id.as_ref() == Some(nft_contract_id)
+            && token_id.contains(DELIMITER)
+        {
+            StakingError::ReceiptNotStakeable.panic();
+        }
+        self.assert_not_paused();
+        self.assert_not_denied(owner_id);
+        let contract_token_id = contract_token_id(nft_contract_id, token_id);
+        if self.blocked_tokens.contains(&contract_token_id) {
+            StakingError::TokenBlocked.panic();
+        }
+        if self.stakes.get(&contract_token_id).is_some() {
+            StakingError::TokenAlreadyStaked.panic();
+        }
+        self.assert_can_stake_more(owner_id);
+        let now = env::block_timestamp();
+        self.internal_insert_stake(Stake {
+            timestamp: now,
+            staked_id: token_id.clone(),
+            owner_id: owner_id.clone(),
+            nft_contract_id: nft_contract_id.clone(),
+            claimed_at: now,
+            rewards_start_at: now + self.config.reward_warmup.0,
+            approval_id,
+            status: if approval_id.is_some() {
+                StakeStatus::Active
+            } else {
+                StakeStatus::Pending
+            },
+        });
+    }
+
+    fn internal_insert_stake(&mut self, stake: Stake) {
+        self.internal_settle_bonus(&stake.owner_id);
+        let contract_token_id = contract_token_id(&stake.nft_contract_id, &stake.staked_id);
+        let mut token_ids = self
+            .stakes_per_owner
+            .get(&stake.owner_id)
+            .unwrap_or_else(|| {
+                UnorderedSet::new(StorageKey::StakesPerOwnerInner {
+                    account_hash: env::sha256(stake.owner_id.as_bytes()),
+                })
+            });
+        token_ids.insert(&contract_token_id);
+        self.stakes_per_owner.insert(&stake.owner_id, &token_ids);
+        self.stakes.insert(&contract_token_id, &stake);
+        if stake.status != StakeStatus::Pending {
+            self.internal_checkpoint_votes(&stake.owner_id, true);
+        }
+        self.internal_refresh_active_sets(&stake.owner_id);
+    }
+
+    /// Removes the stake if it belongs to `owner_id`, returning it.
+    fn internal_remove_stake(
+        &mut self,
+        owner_id: &AccountId,
+        contract_token_id: &ContractTokenId,
+    ) -> Option<Stake> {
+        let stake = self
+            .stakes
+            .get(contract_token_id)
+            .filter(|s| s.owner_id == *owner_id)?;
+        self.internal_settle_before_removal(&stake);
+        self.internal_settle_bonus(owner_id);
+        let stake = self.stakes.remove(contract_token_id).unwrap();
+        self.token_traits.remove(contract_token_id);
+        let mut token_ids = self.stakes_per_owner.get(owner_id).unwrap();
+        token_ids.remove(contract_token_id);
+        if token_ids.is_empty() {
+            self.stakes_per_owner.remove(owner_id);
+        } else {
+            self.stakes_per_owner.insert(owner_id, &token_ids);
+        }
+        if stake.status != StakeStatus::Pending {
+            self.internal_checkpoint_votes(owner_id, false);
+        }
+        self.internal_refresh_active_sets(owner_id);
+        Some(stake)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApprovalReceiver;
+    use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+    use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+
+    const DEFAULT_RATE: u128 = 1_000_000_000_000_000_000_000_000 / 86_400;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn key(token_id: &str) -> ContractTokenId {
+        contract_token_id(&accounts(4), &token_id.to_string())
+    }
+
+    /// Confirms the stakes of `token_ids`, as `resolve_stake_many` does once they were
+    /// transferred.
+    fn confirm_stakes(contract: &mut CrossContract, token_ids: &[&str]) {
+        for token_id in token_ids {
+            contract.internal_resolve_stake(&key(token_id), true);
+        }
+    }
+
+    fn setup_contract() -> (VMContextBuilder, CrossContract) {
+        let context = get_context(accounts(0));
+        testing_env!(context.build());
+        let contract = CrossContract::new(accounts(2), accounts(3), accounts(4), None);
+        (context, contract)
+    }
+
+    #[test]
+    #[should_panic(expected = "The contract is not initialized")]
+    fn test_default() {
+        let context = get_context(accounts(1));
+        testing_env!(context.build());
+        let _contract = CrossContract::default();
+    }
+
+    #[test]
+    fn test_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            10 * DEFAULT_RATE
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E003")]
+    fn test_stake_twice() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E004: Not enough gas attached, attach at least")]
+    fn test_stake_not_enough_gas() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .prepaid_gas(Gas(20 * TGAS))
+            .build());
+        contract.stake("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E011")]
+    fn test_stake_many_empty() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake_many(Vec::new(), None);
+    }
+
+    #[test]
+    fn test_resolve_stake_many_reports_each_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let token_ids = vec!["0".to_string(), "1".to_string()];
+        contract.stake_many(token_ids.clone(), None);
+        assert_eq!(contract.stakes.len(), 2);
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![]), PromiseResult::Failed],
+        );
+        let results = contract.resolve_stake_many(accounts(4), token_ids, vec![true, true]);
+        assert_eq!(results, vec![true, false]);
+        assert!(contract.stakes.get(&key("0")).is_some());
+        assert!(contract.stakes.get(&key("1")).is_none());
+    }
+
+    #[test]
+    fn test_resolve_stake_owners_rolls_back_tokens_of_others() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let token_ids = vec!["0".to_string(), "1".to_string()];
+        contract.stake_many(token_ids.clone(), None);
+
+        let token = |token_id: &str, owner_id: AccountId| {
+            let approvals = std::iter::once((accounts(0), 1)).collect();
+            let token = Token {
+                token_id: token_id.to_string(),
+                owner_id,
+                metadata: None,
+                approved_account_ids: Some(approvals),
+            };
+            PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(token)).unwrap())
+        };
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![token("0", accounts(1)), token("1", accounts(2))],
+        );
+        let result = contract.resolve_stake_owners(accounts(1), accounts(4), token_ids);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(contract.stakes.get(&key("0")).is_some());
+        assert!(contract.stakes.get(&key("1")).is_none());
+
+        testing_env!(
+            context.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![token("0", accounts(2))],
+        );
+        let result = contract.resolve_stake_owners(accounts(1), accounts(4), vec!["0".to_string()]);
+        assert!(matches!(result, PromiseOrValue::Value(owned) if owned == vec![false]));
+        assert!(contract.stakes_per_owner.get(&accounts(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E003")]
+    fn test_stake_many_duplicate_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake_many(vec!["0".to_string(), "0".to_string()], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E003")]
+    fn test_unstake_many_duplicate_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        contract.unstake_many(vec!["0".to_string(), "0".to_string()], None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E004")]
+    fn test_stake_budgets_gas_for_receipt() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.receipt_contract_id = Some(accounts(5));
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .prepaid_gas(Gas(40 * TGAS))
+            .build());
+        contract.stake("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E004")]
+    fn test_unstake_not_enough_gas_for_all_tokens() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+        confirm_stakes(&mut contract, &["0", "1"]);
+
+        testing_env!(context.prepaid_gas(Gas(50 * TGAS)).build());
+        contract.unstake();
+    }
+
+    #[test]
+    fn test_resolve_stake_rolls_back_failed_transfer() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        let block_height = U64(env::block_height());
+        assert_eq!(contract.get_voting_power(accounts(1), block_height), U64(0));
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let results = contract.resolve_stake_many(accounts(4), vec!["0".to_string()], vec![true]);
+        assert_eq!(results, vec![false]);
+        assert!(contract.stakes.get(&key("0")).is_none());
+        assert!(contract.stakes_per_owner.get(&accounts(1)).is_none());
+        assert_eq!(contract.get_voting_power(accounts(1), block_height), U64(0));
+        assert_eq!(contract.get_total_voting_power(block_height), U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E026")]
+    fn test_unstake_pending_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.unstake();
+    }
+
+    #[test]
+    fn test_resolve_unstake_forgets_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])],
+        );
+        assert!(contract.resolve_unstake(accounts(1), accounts(4), "0".to_string()));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.get_claimable("1".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E024")]
+    fn test_transfer_stake_while_unstaking() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        contract.unstake();
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+    }
+
+    #[test]
+    fn test_failed_unstake_keeps_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        contract.unstake();
+        assert_eq!(
+            contract.stakes.get(&key("0")).unwrap().status,
+            StakeStatus::Unstaking
+        );
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.resolve_unstake(accounts(1), accounts(4), "0".to_string()));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+        assert_eq!(
+            contract.stakes.get(&key("0")).unwrap().owner_id,
+            accounts(2)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_stake_without_deposit() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_unstake_without_deposit() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.attached_deposit(0).build());
+        contract.unstake();
+    }
+
+    #[test]
+    #[should_panic(expected = "E001")]
+    fn test_unstake_without_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.unstake();
+    }
+
+    #[test]
+    #[should_panic(expected = "E001")]
+    fn test_claim_without_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.claim(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E002")]
+    fn test_get_claimable_without_stakes() {
+        let (mut context, contract) = setup_contract();
+        testing_env!(context.is_view(true).build());
+        contract.get_claimable("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E002")]
+    fn test_get_claimable_unknown_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.get_claimable("1".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E005")]
+    fn test_upgrade_not_owner() {
+        let (mut context, contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.upgrade();
+    }
+
+    #[test]
+    #[should_panic(expected = "E006")]
+    fn test_new_invalid_config() {
+        testing_env!(get_context(accounts(0)).build());
+        let config = Config {
+            claim_fee_bps: 10_001,
+            ..Config::default()
+        };
+        CrossContract::new(accounts(2), accounts(3), accounts(4), Some(config));
+    }
+
+    #[test]
+    fn test_update_config() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let config = Config {
+            min_lock_duration: 100.into(),
+            ..Config::default()
+        };
+        contract.update_config(config.clone());
+        assert_eq!(contract.get_config(), config);
+    }
+
+    #[test]
+    #[should_panic(expected = "E005")]
+    fn test_update_config_not_owner() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.update_config(Config::default());
+    }
+
+    #[test]
+    fn test_roles_gate_admin_methods() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.grant_role(accounts(1), Role::RewardManager);
+        contract.grant_role(accounts(3), Role::Pauser);
+        assert!(contract.has_role(accounts(2), Role::Owner));
+        assert!(contract.has_role(accounts(1), Role::RewardManager));
+        assert!(!contract.has_role(accounts(1), Role::Operator));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        let config = Config {
+            reward_warmup: 100.into(),
+            ..Config::default()
+        };
+        contract.update_config(config.clone());
+        assert_eq!(contract.get_config(), config);
+
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.pause();
+        assert!(contract.is_paused());
+        contract.unpause();
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.revoke_role(accounts(1), Role::RewardManager);
+        assert!(!contract.has_role(accounts(1), Role::RewardManager));
+    }
+
+    #[test]
+    #[should_panic(expected = "E005")]
+    fn test_reward_manager_cannot_update_other_config() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.grant_role(accounts(1), Role::RewardManager);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.update_config(Config {
+            min_lock_duration: 100.into(),
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "E018")]
+    fn test_stake_while_paused() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.pause();
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E019")]
+    fn test_execute_unknown_change() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.execute_change(42);
+    }
+
+    #[test]
+    fn test_timelocked_change() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.timelock_delay = 100.into();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let id = contract.propose_change(ConfigChange::RewardRate(U128(1)));
+        let referral = contract.propose_change(ConfigChange::ReferralBps(100));
+        let cancelled = contract.propose_change(ConfigChange::ClaimFeeBps(100));
+        contract.cancel_change(cancelled);
+        let pending = contract.get_pending_changes();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].executable_at, 100.into());
+
+        testing_env!(context.block_timestamp(100).build());
+        contract.execute_change(id);
+        contract.execute_change(referral);
+        assert_eq!(contract.get_config().reward_rate, U128(1));
+        assert_eq!(contract.get_config().referral_bps, 100);
+        assert_eq!(contract.get_config().claim_fee_bps, 0);
+        assert!(contract.get_pending_changes().is_empty());
+    }
+
+    #[test]
+    fn test_rate_change_mid_stake_keeps_past_accrual() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(10 * 1_000_000_000)
+            .build());
+        let id = contract.propose_change(ConfigChange::RewardRate(U128(2 * DEFAULT_RATE)));
+        contract.execute_change(id);
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            10 * DEFAULT_RATE
+        );
+
+        // A stake made after the change only earns at the new rate.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(15 * 1_000_000_000)
+            .build());
+        contract.stake("1".to_string(), None);
+
+        testing_env!(context.block_timestamp(20 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            30 * DEFAULT_RATE
+        );
+        assert_eq!(
+            contract.get_claimable("1".to_string(), None),
+            10 * DEFAULT_RATE
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(25 * 1_000_000_000)
+            .build());
+        let id = contract.propose_change(ConfigChange::RewardRate(U128(0)));
+        contract.execute_change(id);
+        testing_env!(context.block_timestamp(40 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            40 * DEFAULT_RATE
+        );
+    }
+
+    #[test]
+    fn test_claim_after_rate_change() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        contract.claim(None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        let id = contract.propose_change(ConfigChange::RewardRate(U128(3 * DEFAULT_RATE)));
+        testing_env!(context.block_timestamp(12 * 1_000_000_000).build());
+        contract.execute_change(id);
+
+        testing_env!(context.block_timestamp(14 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            8 * DEFAULT_RATE
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E020")]
+    fn test_execute_change_before_delay() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.timelock_delay = 100.into();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let id = contract.propose_change(ConfigChange::RewardRate(U128(1)));
+        testing_env!(context.block_timestamp(99).build());
+        contract.execute_change(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E006")]
+    fn test_update_config_rejects_timelocked_fields() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.update_config(Config {
+            claim_fee_bps: 100,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "E006")]
+    fn test_update_config_rejects_reward_mode() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.update_config(Config {
+            reward_mode: RewardMode::Near,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    fn test_reward_mode_change() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let id = contract.propose_change(ConfigChange::RewardMode(RewardMode::Near));
+        contract.execute_change(id);
+        assert_eq!(contract.get_config().reward_mode, RewardMode::Near);
+    }
+
+    #[test]
+    #[should_panic(expected = "E027")]
+    fn test_reward_mode_change_with_rewards_owed() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let id = contract.propose_change(ConfigChange::RewardMode(RewardMode::Near));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.execute_change(id);
+    }
+
+    #[test]
+    fn test_bonus_split_between_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.stake("2".to_string(), None);
+        confirm_stakes(&mut contract, &["0", "1", "2"]);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(accounts(2), U128(100), "bonus".to_string());
+        assert_eq!(contract.get_unsettled_bonus(accounts(1)), U128(66));
+        assert_eq!(contract.get_unsettled_bonus(accounts(2)), U128(33));
+
+        // Stakes made after a drop don't share in it, and the remainder carries over.
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("3".to_string(), None);
+        confirm_stakes(&mut contract, &["3"]);
+        assert_eq!(contract.pending_rewards.get(&accounts(2)), Some(33));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(accounts(2), U128(7), "bonus".to_string());
+        assert_eq!(contract.get_unsettled_bonus(accounts(1)), U128(66 + 4));
+        assert_eq!(contract.get_unsettled_bonus(accounts(2)), U128(4));
+    }
+
+    #[test]
+    fn test_bonus_skips_pending_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.stake("1".to_string(), None);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(accounts(2), U128(100), "bonus".to_string());
+        assert_eq!(contract.get_unsettled_bonus(accounts(1)), U128(100));
+        assert_eq!(contract.get_unsettled_bonus(accounts(2)), U128(0));
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.resolve_stake_many(accounts(4), vec!["1".to_string()], vec![true]);
+        assert_eq!(contract.pending_rewards.get(&accounts(2)), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E005")]
+    fn test_bonus_not_reward_manager() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_on_transfer(accounts(1), U128(100), "bonus".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "E030")]
+    fn test_bonus_in_near_mode() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.reward_mode = RewardMode::Near;
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_on_transfer(accounts(2), U128(100), "bonus".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "E021")]
+    fn test_ft_on_transfer_wrong_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(5)).build());
+        contract.ft_on_transfer(accounts(2), U128(100), "rewards".to_string());
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+        confirm_stakes(&mut contract, &["0", "1"]);
+
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        let report = contract.check_invariants();
+        assert!(!report.ok);
+        assert_eq!(report.account_stakes, U64(2));
+        assert_eq!(report.outstanding_rewards, U128(20 * DEFAULT_RATE));
+        assert_eq!(report.mismatches.len(), 1);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(0)
+            .build());
+        contract.ft_on_transfer(accounts(1), U128(20 * DEFAULT_RATE), "rewards".to_string());
+        assert!(contract.check_invariants().ok);
+
+        // Claiming pays out of the pool and whatever is kept pending stays outstanding.
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.unstake_many(vec!["1".to_string()], None);
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])],
+        );
+        contract.resolve_unstake(accounts(1), accounts(4), "1".to_string());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.claim(None);
+        let report = contract.check_invariants();
+        assert!(report.ok);
+        assert_eq!(report.reward_pool, U128(0));
+
+        let mut token_ids = contract.stakes_per_owner.get(&accounts(1)).unwrap();
+        token_ids.clear();
+        contract.stakes_per_owner.insert(&accounts(1), &token_ids);
+        let report = contract.check_invariants();
+        assert_eq!(report.account_stakes, U64(0));
+        assert_eq!(report.mismatches.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "E003")]
+    fn test_rescue_staked_nft() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.rescue_nft(accounts(5), "0".to_string(), accounts(2));
+        contract.rescue_nft(accounts(4), "0".to_string(), accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "E005")]
+    fn test_rescue_nft_not_owner() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.rescue_nft(accounts(4), "0".to_string(), accounts(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "E028")]
+    fn test_rescue_nft_not_held() {
+        let (mut context, mut contract) = setup_contract();
+        // The contract is merely approved for the token.
+        let approvals = std::iter::once((accounts(0), 1)).collect();
+        let token = Token {
+            token_id: "0".to_string(),
+            owner_id: accounts(1),
+            metadata: None,
+            approved_account_ids: Some(approvals),
+        };
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&Some(token)).unwrap()
+            )],
+        );
+        contract.resolve_rescue_nft(accounts(4), "0".to_string(), accounts(2));
+    }
+
+    #[test]
+    fn test_rescue_nft_logged_after_transfer() {
+        let (mut context, mut contract) = setup_contract();
+        for (result, transferred) in [
+            (PromiseResult::Failed, false),
+            (PromiseResult::Successful(vec![]), true),
+        ] {
+            testing_env!(
+                context
+                    .predecessor_account_id(accounts(0))
+                    .attached_deposit(0)
+                    .build(),
+                VMConfig::test(),
+                RuntimeFeesConfig::test(),
+                Default::default(),
+                vec![result],
+            );
+            assert_eq!(
+                contract.resolve_nft_rescued(accounts(4), "0".to_string(), accounts(2)),
+                transferred
+            );
+        }
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nft_staking","version":"1.0.0","event":"nft_rescued","data":[{"nft_contract_id":"eugene","token_id":"0","receiver_id":"charlie"}]}"#,
+            ]
+        );
+    }
+
+    fn resolve_rescue_ft(
+        context: &mut VMContextBuilder,
+        contract: &mut CrossContract,
+        balance: u128,
+        amount: u128,
+    ) {
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(balance)).unwrap()
+            )],
+        );
+        contract.resolve_rescue_ft(accounts(3), accounts(2), U128(amount));
+    }
+
+    #[test]
+    #[should_panic(expected = "E023")]
+    fn test_rescue_ft_keeps_reward_pool() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_on_transfer(accounts(1), U128(100), "rewards".to_string());
+
+        resolve_rescue_ft(&mut context, &mut contract, 150, 50);
+        resolve_rescue_ft(&mut context, &mut contract, 150, 51);
+    }
+
+    #[test]
+    #[should_panic(expected = "E029")]
+    fn test_rescue_ft_balance_unavailable() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.resolve_rescue_ft(accounts(3), accounts(2), U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "E007")]
+    fn test_stake_collection_not_allowed() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), Some(accounts(5)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E008")]
+    fn test_stake_too_many() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.max_stakes_per_account = 1;
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E015")]
+    fn test_stake_pool_full() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.max_total_stakes = Some(1.into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.stake("1".to_string(), None);
+    }
+
+    #[test]
+    fn test_get_capacity() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.max_total_stakes = Some(3.into());
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+
+        let capacity = contract.get_capacity(accounts(1));
+        assert_eq!(capacity.account_staked, 2);
+        assert_eq!(capacity.total_staked, 2.into());
+        assert_eq!(capacity.available, 1);
+        assert_eq!(contract.get_capacity(accounts(2)).available, 1);
+    }
+
+    #[test]
+    fn test_unstake_many_keeps_other_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_approve("0".to_string(), accounts(1), 1, String::new());
+        contract.nft_on_approve("1".to_string(), accounts(1), 2, String::new());
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.unstake_many(vec!["0".to_string()], None);
+        assert!(contract.stakes.get(&key("0")).is_none());
+        assert!(contract.stakes.get(&key("1")).is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "E002")]
+    fn test_unstake_many_not_owner() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.unstake_many(vec!["0".to_string()], None);
+    }
+
+    #[test]
+    fn test_warmup_delays_rewards() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.reward_warmup = (5 * 1_000_000_000).into();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        let stake = contract.get_stake("0".to_string(), None).unwrap();
+        assert_eq!(stake.rewards_start_at, (5 * 1_000_000_000).into());
+
+        testing_env!(context.block_timestamp(4 * 1_000_000_000).build());
+        assert_eq!(contract.get_claimable("0".to_string(), None), 0);
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            5 * DEFAULT_RATE
+        );
+    }
+
+    #[test]
+    fn test_deny_and_allow_account() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.deny_account(accounts(1));
+        assert!(contract.is_account_denied(accounts(1)));
+        assert_eq!(contract.get_denied_accounts(None, None), vec![accounts(1)]);
+
+        contract.allow_account(accounts(1));
+        assert!(!contract.is_account_denied(accounts(1)));
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nft_staking","version":"1.0.0","event":"account_denied","data":[{"account_id":"bob"}]}"#,
+                r#"EVENT_JSON:{"standard":"nft_staking","version":"1.0.0","event":"account_allowed","data":[{"account_id":"bob"}]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E016")]
+    fn test_claim_denied_account() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.deny_account(accounts(1));
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(10)
+            .build());
+        contract.claim(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E016")]
+    fn test_transfer_stake_denied_account() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.deny_account(accounts(1));
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.transfer_stake("0".to_string(), accounts(3), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E017")]
+    fn test_stake_blocked_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.block_token("0".to_string(), None);
+        assert!(contract.is_token_blocked("0".to_string(), None));
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E009")]
+    fn test_unstake_locked() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.min_lock_duration = 100.into();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.block_timestamp(99).build());
+        contract.unstake();
+    }
+
+    #[test]
+    fn test_claim_keeps_fee_and_restores_failed_payout() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.claim_fee_bps = 1_000;
+        contract.config.allowed_collections = vec![accounts(5)];
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("0".to_string(), Some(accounts(5)));
+        confirm_stakes(&mut contract, &["0"]);
+        contract.internal_resolve_stake(&contract_token_id(&accounts(5), &"0".to_string()), true);
+
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        contract.claim(None);
+        assert_eq!(
+            contract.get_claimable("0".to_string(), Some(accounts(5))),
+            0
+        );
+        // The fee is only credited once the payout went through.
+        assert_eq!(contract.get_treasury_balance(), U128(0));
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])],
+        );
+        assert!(contract.resolve_claim(
+            accounts(1),
+            (20 * DEFAULT_RATE).into(),
+            (2 * DEFAULT_RATE).into(),
+            RewardMode::Ft,
+        ));
+        assert_eq!(contract.get_treasury_balance(), U128(2 * DEFAULT_RATE));
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.resolve_claim(
+            accounts(1),
+            (20 * DEFAULT_RATE).into(),
+            (2 * DEFAULT_RATE).into(),
+            RewardMode::Ft,
+        ));
+        assert_eq!(contract.get_treasury_balance(), U128(2 * DEFAULT_RATE));
+        assert_eq!(
+            contract.pending_rewards.get(&accounts(1)),
+            Some(20 * DEFAULT_RATE)
+        );
+    }
+
+    #[test]
+    fn test_claim_in_near_mode() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.reward_mode = RewardMode::Near;
+        contract.config.claim_fee_bps = 1_000;
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(100 * DEFAULT_RATE)
+            .build());
+        contract.deposit_near_rewards();
+        assert_eq!(contract.get_near_reward_pool(), U128(100 * DEFAULT_RATE));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        contract.claim(None);
+        assert_eq!(contract.get_near_reward_pool(), U128(91 * DEFAULT_RATE));
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.resolve_claim(
+            accounts(1),
+            (10 * DEFAULT_RATE).into(),
+            DEFAULT_RATE.into(),
+            RewardMode::Near,
+        );
+        assert_eq!(contract.get_near_reward_pool(), U128(100 * DEFAULT_RATE));
+    }
+
+    #[test]
+    #[should_panic(expected = "E022")]
+    fn test_claim_in_near_mode_without_pool() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.reward_mode = RewardMode::Near;
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        contract.claim(None);
+    }
+
+    #[test]
+    fn test_transfer_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        testing_env!(context.build());
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+
+        let stake = contract.stakes.get(&key("0")).unwrap();
+        assert_eq!(stake.owner_id, accounts(2));
+        assert!(contract.stakes_per_owner.get(&accounts(1)).is_none());
+        assert_eq!(
+            contract.stakes_per_owner.get(&accounts(2)).unwrap().len(),
+            1
+        );
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"nft_staking","version":"1.0.0","event":"stake_transfer","data":[{"old_owner_id":"bob","new_owner_id":"charlie","nft_contract_id":"eugene","token_id":"0"}]}"#
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E002")]
+    fn test_transfer_stake_not_owner() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+    }
+
+    #[test]
+    fn test_voting_power_checkpoints() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .block_index(10)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.stake("1".to_string(), None);
+        confirm_stakes(&mut contract, &["0", "1"]);
+
+        testing_env!(context.block_index(20).build());
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+
+        assert_eq!(contract.get_voting_power(accounts(1), 9.into()), 0.into());
+        assert_eq!(contract.get_voting_power(accounts(1), 15.into()), 2.into());
+        assert_eq!(contract.get_voting_power(accounts(1), 20.into()), 1.into());
+        assert_eq!(contract.get_voting_power(accounts(2), 20.into()), 1.into());
+        assert_eq!(contract.get_total_voting_power(15.into()), 2.into());
+        assert_eq!(contract.get_total_voting_power(25.into()), 2.into());
+    }
+
+    #[test]
+    #[should_panic(expected = "E026")]
+    fn test_transfer_pending_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+    }
+
+    #[test]
+    fn test_resolve_stake_owners_rolls_back_transferred_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        // A pending stake moved to another account, as `transfer_stake` used to allow.
+        let mut stake = contract
+            .internal_remove_stake(&accounts(1), &key("0"))
+            .unwrap();
+        stake.owner_id = accounts(2);
+        contract.internal_insert_stake(stake);
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&None::<Token>).unwrap()
+            )],
+        );
+        let result = contract.resolve_stake_owners(accounts(1), accounts(4), vec!["0".to_string()]);
+        assert!(matches!(result, PromiseOrValue::Value(owned) if owned == vec![false]));
+        assert!(contract.stakes.get(&key("0")).is_none());
+        assert!(contract.stakes_per_owner.get(&accounts(2)).is_none());
+    }
+
+    #[test]
+    fn test_referral() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.referral_bps = 1_000;
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        let msg = format!(r#"{{"referrer_id":"{}"}}"#, accounts(2));
+        contract.nft_on_transfer(accounts(1), accounts(1), "0".to_string(), msg);
+        assert!(contract.stakes.get(&key("0")).is_some());
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .block_timestamp(10 * 1_000_000_000)
+            .build());
+        contract.claim(None);
+        let stats = contract.get_referral_stats(accounts(2));
+        assert_eq!(stats.referees, 1);
+        assert_eq!(stats.earned, U128(DEFAULT_RATE));
+        assert_eq!(
+            contract.get_referral_stats(accounts(1)).referrer_id,
+            Some(accounts(2))
+        );
+
+        // The referrer can claim the share without stakes of its own.
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.claim(None);
+        assert!(contract.pending_rewards.get(&accounts(2)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E013")]
+    fn test_nft_on_transfer_invalid_msg() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_transfer(
+            accounts(1),
+            accounts(1),
+            "0".to_string(),
+            "referrer".to_string(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E012")]
+    fn test_transfer_soft_stake() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_approve("0".to_string(), accounts(1), 1, String::new());
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.transfer_stake("0".to_string(), accounts(2), None);
+    }
+
+    #[test]
+    fn test_nft_on_approve_soft_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_approve("0".to_string(), accounts(1), 1, String::new());
+        assert_eq!(contract.stakes.get(&key("0")).unwrap().approval_id, Some(1));
+
+        // A new owner approving the token replaces the stale soft stake.
+        contract.nft_on_approve("0".to_string(), accounts(2), 2, String::new());
+        assert_eq!(
+            contract.stakes.get(&key("0")).unwrap().owner_id,
+            accounts(2)
+        );
+        assert!(contract.stakes_per_owner.get(&accounts(1)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E025")]
+    fn test_receipt_not_stakeable() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.receipt_contract_id = Some(accounts(4));
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_approve(key("0"), accounts(1), 1, String::new());
+    }
+
+    #[test]
+    fn test_checked_claim_keeps_rewards_pending_until_resolved() {
+        let (mut context, mut contract) = setup_contract();
+        contract.ft_reward_pool = 100 * DEFAULT_RATE;
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_approve("1".to_string(), accounts(1), 1, String::new());
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(10 * 1_000_000_000)
+            .build());
+        contract.claim(None);
+        // Nothing is lost if the callback never completes.
+        assert_eq!(
+            contract.pending_rewards.get(&accounts(1)),
+            Some(10 * DEFAULT_RATE)
+        );
+        assert_eq!(contract.unpaid_rewards, 10 * DEFAULT_RATE);
+
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        // Only what is still pending is paid out, e.g. after another claim took the rest.
+        contract.resolve_checked_claim(
+            accounts(1),
+            vec![key("1")],
+            U128(20 * DEFAULT_RATE),
+            0.into(),
+            false,
+            false,
+        );
+        assert_eq!(contract.pending_rewards.get(&accounts(1)), None);
+        assert_eq!(contract.unpaid_rewards, 0);
+        assert!(near_sdk::test_utils::get_logs()[0]
+            .contains(&format!(r#""amount":"{}""#, 10 * DEFAULT_RATE)));
+    }
+
+    #[test]
+    fn test_resolve_checked_claim_voids_moved_token() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(4)).build());
+        contract.nft_on_approve("0".to_string(), accounts(1), 1, String::new());
+        contract.nft_on_approve("1".to_string(), accounts(1), 2, String::new());
+
+        let token = |token_id: &str, owner_id: AccountId, approval_id: u64| {
+            let approvals = std::iter::once((accounts(0), approval_id)).collect();
+            let token = Token {
+                token_id: token_id.to_string(),
+                owner_id,
+                metadata: None,
+                approved_account_ids: Some(approvals),
+            };
+            PromiseResult::Successful(near_sdk::serde_json::to_vec(&Some(token)).unwrap())
+        };
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .block_timestamp(10 * 1_000_000_000)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![token("0", accounts(1), 1), token("1", accounts(3), 2)],
+        );
+        contract.resolve_checked_claim(
+            accounts(1),
+            vec![key("0"), key("1")],
+            0.into(),
+            0.into(),
+            false,
+            false,
+        );
+        assert_eq!(
+            contract.stakes.get(&key("0")).unwrap().claimed_at,
+            10 * 1_000_000_000
+        );
+        assert!(contract.stakes.get(&key("1")).is_none());
+    }
+
+    #[test]
+    fn test_resolve_checked_claim_applies_boost() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.boost_tiers = vec![
+            BoostTier {
+                min_balance: U128(100),
+                multiplier_bps: 15_000,
+            },
+            BoostTier {
+                min_balance: U128(1_000),
+                multiplier_bps: 20_000,
+            },
+        ];
+        contract.config.vesting_duration = 100.into();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        // With boost tiers set, the claim waits for the FT balance before paying out.
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        contract.claim(None);
+        assert!(contract.get_vesting(accounts(1)).schedules.is_empty());
+
+        let balance = near_sdk::serde_json::to_vec(&U128(500)).unwrap();
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(balance)],
+        );
+        contract.resolve_checked_claim(
+            accounts(1),
+            Vec::new(),
+            U128(10 * DEFAULT_RATE),
+            0.into(),
+            false,
+            true,
+        );
+        let reward = 15 * DEFAULT_RATE;
+        assert_eq!(
+            contract.get_vesting(accounts(1)).schedules[0].amount,
+            U128(reward)
+        );
+        assert_eq!(
+            near_sdk::test_utils::get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"nft_staking","version":"1.0.0","event":"claim","data":[{{"owner_id":"bob","amount":"{}","fee":"0","multiplier_bps":15000,"vested":true}}]}}"#,
+                reward
+            )]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E006")]
+    fn test_update_config_unsorted_boost_tiers() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        let boost_tiers = vec![
+            BoostTier {
+                min_balance: U128(1_000),
+                multiplier_bps: 20_000,
+            },
+            BoostTier {
+                min_balance: U128(100),
+                multiplier_bps: 15_000,
+            },
+        ];
+        contract.update_config(Config {
+            boost_tiers,
+            ..Config::default()
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "E006")]
+    fn test_set_stake_set_over_max_sets() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.max_sets = 2;
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        for name in ["a", "b", "b", "c"] {
+            let set = StakeSet {
+                nft_contract_id: accounts(4),
+                members: SetMembers::TokenIds(vec![name.to_string()]),
+                bonus_bps: 1_000,
+            };
+            contract.set_stake_set(name.to_string(), set);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "E004")]
+    fn test_unstake_budgets_gas_for_sets() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        for index in 0..10 {
+            let set = StakeSet {
+                nft_contract_id: accounts(4),
+                members: SetMembers::TokenIds(vec![index.to_string()]),
+                bonus_bps: 1_000,
+            };
+            contract.set_stake_set(index.to_string(), set);
+        }
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        // Enough for the transfer and its callback, but not for refreshing ten sets.
+        testing_env!(context.prepaid_gas(Gas(40 * TGAS)).build());
+        contract.unstake();
+    }
+
+    #[test]
+    fn test_complete_set_earns_bonus_until_broken() {
+        let (mut context, mut contract) = setup_contract();
+        let set = StakeSet {
+            nft_contract_id: accounts(4),
+            members: SetMembers::TokenIds(vec!["0".to_string(), "1".to_string()]),
+            bonus_bps: 5_000,
+        };
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.set_stake_set("pair".to_string(), set);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+        assert!(contract.get_active_sets(accounts(1)).is_empty());
+        contract.stake("1".to_string(), None);
+        assert!(contract.get_active_sets(accounts(1)).is_empty());
+        confirm_stakes(&mut contract, &["1"]);
+        assert_eq!(
+            contract.get_active_sets(accounts(1)),
+            vec!["pair".to_string()]
+        );
+
+        testing_env!(context.block_timestamp(10 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            15 * DEFAULT_RATE
+        );
+
+        // Breaking the set settles the bonus earned so far and stops it from then on.
+        contract.transfer_stake("1".to_string(), accounts(2), None);
+        assert!(contract.get_active_sets(accounts(1)).is_empty());
+        assert_eq!(
+            contract.settled_rewards.get(&accounts(1)),
+            Some(30 * DEFAULT_RATE)
+        );
+        testing_env!(context.block_timestamp(20 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            10 * DEFAULT_RATE
+        );
+    }
+
+    #[test]
+    fn test_set_removal_applies_from_next_refresh() {
+        let (mut context, mut contract) = setup_contract();
+        let set = StakeSet {
+            nft_contract_id: accounts(4),
+            members: SetMembers::TokenIds(vec!["0".to_string()]),
+            bonus_bps: 5_000,
+        };
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.set_stake_set("single".to_string(), set);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        // The bonus earned before the set was removed is kept.
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(10 * 1_000_000_000)
+            .build());
+        contract.remove_stake_set("single".to_string());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            15 * DEFAULT_RATE
+        );
+        contract.refresh_active_sets(accounts(1));
+        assert!(contract.get_active_sets(accounts(1)).is_empty());
+        assert_eq!(
+            contract.settled_rewards.get(&accounts(1)),
+            Some(15 * DEFAULT_RATE)
+        );
+
+        testing_env!(context.block_timestamp(20 * 1_000_000_000).build());
+        assert_eq!(
+            contract.get_claimable("0".to_string(), None),
+            10 * DEFAULT_RATE
+        );
+    }
+
+    #[test]
+    fn test_resolve_token_traits_completes_trait_set() {
+        let (mut context, mut contract) = setup_contract();
+        let members = SetMembers::Trait {
+            trait_type: "color".to_string(),
+            value: "red".to_string(),
+            size: 1,
+        };
+        let set = StakeSet {
+            nft_contract_id: accounts(4),
+            members,
+            bonus_bps: 1_000,
+        };
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.set_stake_set("red".to_string(), set);
+
+        testing_env!(context.predecessor_account_id(accounts(1)).build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        let metadata = TokenMetadata {
+            title: None,
+            description: None,
+            media: None,
+            media_hash: None,
+            copies: None,
+            issued_at: None,
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: Some(r#"{"color":"red"}"#.to_string()),
+            reference: None,
+            reference_hash: None,
+        };
+        let token = Token {
+            token_id: "0".to_string(),
+            owner_id: accounts(0),
+            metadata: Some(metadata),
+            approved_account_ids: None,
+        };
+        testing_env!(
+            context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(0)
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&Some(token)).unwrap()
+            )],
+        );
+        contract.resolve_token_traits(key("0"));
+        assert_eq!(
+            contract.get_active_sets(accounts(1)),
+            vec!["red".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_claim_vests_rewards() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.vesting_cliff = 10.into();
+        contract.config.vesting_duration = 100.into();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        confirm_stakes(&mut contract, &["0"]);
+
+        testing_env!(context.block_timestamp(1_000_000_000).build());
+        contract.claim(None);
+        assert_eq!(contract.get_vesting(accounts(1)).withdrawable, U128(0));
+
+        testing_env!(context.block_timestamp(1_000_000_050).build());
+        assert_eq!(
+            contract.get_vesting(accounts(1)).withdrawable,
+            U128(DEFAULT_RATE / 2)
+        );
+        contract.withdraw_vested();
+        let vesting = contract.get_vesting(accounts(1));
+        assert_eq!(vesting.withdrawable, U128(0));
+        assert_eq!(vesting.schedules[0].withdrawn, U128(DEFAULT_RATE / 2));
+
+        testing_env!(context.block_timestamp(1_000_000_100).build());
+        contract.withdraw_vested();
+        assert!(contract.get_vesting(accounts(1)).schedules.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E010")]
+    fn test_withdraw_vested_before_cliff() {
+        let (mut context, mut contract) = setup_contract();
+        contract.config.vesting_cliff = 10.into();
+        contract.config.vesting_duration = 100.into();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+
+        testing_env!(context.block_timestamp(1_000_000_000).build());
+        contract.claim(None);
+        contract.withdraw_vested();
+    }
+
+    #[test]
+    #[should_panic(expected = "E005")]
+    fn test_withdraw_treasury_not_owner() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.withdraw_treasury(accounts(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E014")]
+    fn test_withdraw_treasury_over_balance() {
+        let (mut context, mut contract) = setup_contract();
+        contract.treasury_balance = 10;
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.withdraw_treasury(accounts(2), Some(U128(11)));
+    }
+
+    #[test]
+    fn test_migrate_from_v1() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        // State as written by the first release.
+        #[derive(BorshSerialize)]
+        struct V1Layout {
+            nft_account: AccountId,
+            ft_account: AccountId,
+            staked: UnorderedMap<AccountId, Vector<StakeV1>>,
+            unstaked: UnorderedMap<AccountId, Vector<u128>>,
+        }
+        let mut old = V1Layout {
+            nft_account: accounts(4),
+            ft_account: accounts(3),
+            staked: UnorderedMap::new(b"staked".to_vec()),
+            unstaked: UnorderedMap::new(b"unstaked".to_vec()),
+        };
+        // V1 gave every account's vector the same prefix.
+        for (account_id, token_ids) in [(accounts(1), vec!["0"]), (accounts(2), vec!["1", "2"])] {
+            let mut staked: Vector<StakeV1> = Vector::new(b"new_vec".to_vec());
+            for token_id in token_ids {
+                staked.push(&StakeV1 {
+                    timestamp: 5_000_000_000,
+                    staked_id: token_id.to_string(),
+                    owner_id: account_id.clone(),
                 });
-                (current_timestamp - staked_timestamp).into()
-            }
-            None => {
-                log!("{}", "Cannot get claimable amount");
-                0
             }
+            old.staked.insert(&account_id, &staked);
+            old.unstaked
+                .insert(&account_id, &Vector::new(b"new_vec".to_vec()));
+        }
+        // An unstake pushed a zero over the first record of every account.
+        let mut unstaked: Vector<u128> = Vector::new(b"new_vec".to_vec());
+        unstaked.push(&0);
+        old.unstaked.insert(&accounts(2), &unstaked);
+        env::state_write(&old);
+
+        let mut contract = CrossContract::migrate();
+        assert_eq!(contract.owner_id, accounts(0));
+        assert_eq!(contract.nft_account, accounts(4));
+        assert_eq!(contract.ft_account, accounts(3));
+        assert_eq!(contract.stakes.len(), 1);
+        let stake = contract.stakes.get(&key("2")).unwrap();
+        assert_eq!(stake.owner_id, accounts(2));
+        assert_eq!(stake.timestamp, 5_000_000_000);
+        assert_eq!(stake.status, StakeStatus::Pending);
+        assert_eq!(contract.get_unverified_stakes(None, None), vec![key("2")]);
+        assert!(contract.stakes_per_owner.get(&accounts(1)).is_none());
+        assert!(old.staked.get(&accounts(1)).is_none());
+        assert!(old.unstaked.get(&accounts(2)).is_none());
+        for index in 0..2u64 {
+            assert!(!env::storage_has_key(
+                &[b"new_vec".as_slice(), &index.to_le_bytes()].concat()
+            ));
         }
+
+        contract.verify_migrated_stakes(vec!["2".to_string(), "0".to_string()]);
+        let token = Token {
+            token_id: "2".to_string(),
+            owner_id: accounts(0),
+            metadata: None,
+            approved_account_ids: None,
+        };
+        testing_env!(
+            context.build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&Some(token)).unwrap()
+            )],
+        );
+        let verified = contract.resolve_migrated_stakes(vec!["2".to_string()]);
+        assert_eq!(verified, vec![true]);
+        assert_eq!(
+            contract.stakes.get(&key("2")).unwrap().status,
+            StakeStatus::Active
+        );
+        assert!(contract.get_unverified_stakes(None, None).is_empty());
+
+        testing_env!(context
+            .block_timestamp(15_000_000_000)
+            .is_view(true)
+            .build());
+        assert_eq!(
+            contract.get_claimable("2".to_string(), None),
+            10 * DEFAULT_RATE
+        );
     }
 
-    pub fn transfer_money(&mut self, account_id: AccountId, amount: u64) {
-        Promise::new(account_id).transfer(amount as u128);
+    #[test]
+    fn test_verify_migrated_stakes_drops_tokens_not_held() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        for token_id in ["0", "1"] {
+            contract.internal_add_stake(&accounts(1), &accounts(4), &token_id.to_string(), None);
+            contract.unverified_stakes.insert(&key(token_id));
+        }
+
+        testing_env!(
+            context.predecessor_account_id(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(near_sdk::serde_json::to_vec(&None::<Token>).unwrap()),
+                PromiseResult::Failed,
+            ],
+        );
+        let verified = contract.resolve_migrated_stakes(vec!["0".to_string(), "1".to_string()]);
+        assert_eq!(verified, vec![false, false]);
+        assert!(contract.stakes.get(&key("0")).is_none());
+        assert_eq!(
+            contract.stakes.get(&key("1")).unwrap().status,
+            StakeStatus::Pending
+        );
+        assert_eq!(contract.get_unverified_stakes(None, None), vec![key("1")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E011")]
+    fn test_verify_migrated_stakes_skips_other_stakes() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        contract.verify_migrated_stakes(vec!["0".to_string()]);
+    }
+
+    #[test]
+    fn test_migrate_current_version_keeps_state() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(ONE_YOCTO)
+            .build());
+        contract.stake("0".to_string(), None);
+        env::state_write(&contract);
+
+        let migrated = CrossContract::migrate();
+        assert_eq!(migrated.owner_id, accounts(2));
+        assert_eq!(
+            migrated.stakes.get(&key("0")).unwrap().owner_id,
+            accounts(1)
+        );
     }
 }
@@ -0,0 +1,243 @@
+use crate::*;
+
+/// Storage key holding the version of the stored contract state. States written before
+/// versioning was introduced don't have it.
+const STATE_VERSION_KEY: &[u8] = b"STATE_VERSION";
+/// Version of the layout defined by [`CrossContract`].
+const STATE_VERSION: u8 = 2;
+/// Least gas left for `migrate` after the new code is deployed.
+const GAS_FOR_MIGRATE: Gas = Gas(20 * TGAS);
+
+/// State layout of the first release: stakes were kept per account in a `Vector`, next to an
+/// `unstaked` map that was never read.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CrossContractV1 {
+    pub nft_account: AccountId,
+    pub ft_account: AccountId,
+    pub staked: UnorderedMap<AccountId, LegacyVector>,
+    pub unstaked: UnorderedMap<AccountId, LegacyVector>,
+}
+
+/// Borsh layout of a `near_sdk::collections::Vector`. V1 gave every account's vectors the same
+/// storage prefix, so their lengths don't match what is stored and the SDK type would panic
+/// while reading or clearing them.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyVector {
+    len: u64,
+    prefix: Vec<u8>,
+}
+
+impl LegacyVector {
+    fn element_key(&self, index: u64) -> Vec<u8> {
+        [self.prefix.as_slice(), &index.to_le_bytes()].concat()
+    }
+
+    /// Elements that are still in storage and can be read as `T`. Another vector with the
+    /// same prefix may have overwritten a slot with a value of its own type.
+    fn elements<T: BorshDeserialize>(&self) -> Vec<T> {
+        (0..self.len)
+            .filter_map(|index| env::storage_read(&self.element_key(index)))
+            .filter_map(|raw| T::try_from_slice(&raw).ok())
+            .collect()
+    }
+
+    fn clear(&self) {
+        for index in 0..self.len {
+            env::storage_remove(&self.element_key(index));
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StakeV1 {
+    pub timestamp: u64,
+    pub staked_id: TokenId,
+    pub owner_id: AccountId,
+}
+
+/// Every state layout the contract has been deployed with.
+#[allow(clippy::large_enum_variant)]
+pub enum VersionedCrossContract {
+    V1(CrossContractV1),
+    V2(CrossContract),
+}
+
+impl VersionedCrossContract {
+    /// Reads the stored state, whichever layout it has.
+    pub fn read() -> Self {
+        match env::storage_read(STATE_VERSION_KEY).as_deref() {
+            None => Self::V1(read_state()),
+            Some([STATE_VERSION]) => Self::V2(read_state()),
+            Some(_) => env::panic_str("Unknown contract state version"),
+        }
+    }
+
+    /// Converts the state to the current layout.
+    pub fn into_current(self) -> CrossContract {
+        match self {
+            Self::V1(old) => migrate_v1(old),
+            Self::V2(current) => current,
+        }
+    }
+}
+
+pub(crate) fn write_state_version() {
+    env::storage_write(STATE_VERSION_KEY, &[STATE_VERSION]);
+}
+
+fn read_state<T: BorshDeserialize>() -> T {
+    env::state_read().unwrap_or_else(|| env::panic_str("The contract is not initialized"))
+}
+
+/// The V1 per-account vectors could share storage, so every record is re-keyed by the owner it
+/// carries and duplicates are dropped, as are records without a valid owner, e.g. overwritten by
+/// the `0u128` pushed to an `unstaked` vector. As V1 never checked that the tokens reached it,
+/// the stakes are migrated as pending, and only confirmed by `verify_migrated_stakes`. The owner
+/// of a migrated contract is the contract account and it starts with the default [`Config`].
+fn migrate_v1(mut old: CrossContractV1) -> CrossContract {
+    let mut this = CrossContract::new(
+        env::current_account_id(),
+        old.ft_account,
+        old.nft_account,
+        None,
+    );
+
+    let old_stakes: Vec<LegacyVector> = old.staked.values().collect();
+    for stake in old_stakes
+        .iter()
+        .flat_map(|stakes| stakes.elements::<StakeV1>())
+    {
+        if !env::is_valid_account_id(stake.owner_id.as_bytes()) {
+            continue;
+        }
+        let contract_token_id = contract_token_id(&this.nft_account, &stake.staked_id);
+        if this.stakes.get(&contract_token_id).is_none() {
+            this.unverified_stakes.insert(&contract_token_id);
+            this.internal_insert_stake(Stake {
+                timestamp: stake.timestamp,
+                staked_id: stake.staked_id,
+                owner_id: stake.owner_id,
+                nft_contract_id: this.nft_account.clone(),
+                claimed_at: stake.timestamp,
+                rewards_start_at: stake.timestamp,
+                approval_id: None,
+                status: StakeStatus::Pending,
+            });
+        }
+    }
+    old_stakes.iter().for_each(LegacyVector::clear);
+    old.staked.clear();
+
+    old.unstaked.values().for_each(|unstaked| unstaked.clear());
+    old.unstaked.clear();
+
+    this
+}
+
+#[near_bindgen]
+impl CrossContract {
+    /// Deploys the contract code passed as the raw call input and then calls `migrate` on the
+    /// new code, with the gas left. Only owners can upgrade the contract.
+    pub fn upgrade(&self) -> Promise {
+        self.assert_role(Role::Owner);
+        assert_enough_gas(GAS_FOR_MIGRATE);
+        let code = env::input().unwrap_or_else(|| env::panic_str("Expected the contract code"));
+        let gas_for_migrate = env::prepaid_gas().0 - env::used_gas().0 - GAS_FOR_EXECUTION.0;
+        Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call("migrate".to_string(), Vec::new(), 0, Gas(gas_for_migrate))
+    }
+
+    /// Converts the stored state, whatever its version, to the current layout.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let this = VersionedCrossContract::read().into_current();
+        write_state_version();
+        this
+    }
+
+    /// Reads the owner of the migrated stakes among `token_ids` of `nft_account` with
+    /// `nft_token`, confirming the stakes whose token the contract holds and dropping the
+    /// others. Other ids are skipped. Anyone can call this.
+    pub fn verify_migrated_stakes(&mut self, token_ids: Vec<TokenId>) -> Promise {
+        let mut token_ids: Vec<TokenId> = token_ids
+            .into_iter()
+            .filter(|token_id| {
+                self.unverified_stakes
+                    .contains(&contract_token_id(&self.nft_account, token_id))
+            })
+            .collect();
+        token_ids.sort();
+        token_ids.dedup();
+        if token_ids.is_empty() {
+            StakingError::EmptyBatch.panic();
+        }
+        let count = token_ids.len() as u64;
+        let gas_for_resolve = self.gas_for_resolve_stake_many(&self.nft_account, count);
+        assert_enough_gas(Gas(count * GAS_FOR_NFT_TOKEN.0 + gas_for_resolve));
+        token_ids
+            .iter()
+            .map(|token_id| {
+                nftext::nft_token(
+                    token_id.clone(),
+                    self.nft_account.clone(),
+                    0,
+                    GAS_FOR_NFT_TOKEN,
+                )
+            })
+            .reduce(Promise::and)
+            .unwrap()
+            .then(ext_self::resolve_migrated_stakes(
+                token_ids,
+                env::current_account_id(),
+                0,
+                Gas(gas_for_resolve),
+            ))
+    }
+
+    /// Confirms the migrated stakes whose token is held by the contract and drops those whose
+    /// token isn't. Stakes whose owner couldn't be read are left to be verified again.
+    #[private]
+    pub fn resolve_migrated_stakes(&mut self, token_ids: Vec<TokenId>) -> Vec<bool> {
+        require!(
+            env::promise_results_count() == token_ids.len() as u64,
+            "Contract expected a result for every token"
+        );
+        token_ids
+            .iter()
+            .enumerate()
+            .map(|(index, token_id)| {
+                let contract_token_id = contract_token_id(&self.nft_account, token_id);
+                let held = match env::promise_result(index as u64) {
+                    PromiseResult::Successful(value) => {
+                        serde_json::from_slice::<Option<Token>>(&value)
+                            .map(|token| {
+                                token.filter(|token| token.owner_id == env::current_account_id())
+                            })
+                            .ok()
+                    }
+                    _ => None,
+                };
+                match held {
+                    Some(token) if self.unverified_stakes.remove(&contract_token_id) => {
+                        self.internal_resolve_stake(&contract_token_id, token.is_some());
+                        token.is_some()
+                    }
+                    _ => false,
+                }
+            })
+            .collect()
+    }
+
+    /// Stakes migrated from V1 that haven't been verified yet, as
+    /// `"<nft_contract_id>:<token_id>"`, `limit` of them (all if omitted) starting at
+    /// `from_index`.
+    pub fn get_unverified_stakes(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<u32>,
+    ) -> Vec<ContractTokenId> {
+        denylist::paginate(self.unverified_stakes.iter(), from_index, limit)
+    }
+}
@@ -0,0 +1,88 @@
+use crate::*;
+
+/// Rewards, vested rewards and the treasury are paid out in the reward FT or, when
+/// `config.reward_mode` is `near`, in NEAR from a pool funded with `deposit_near_rewards`.
+/// Balances aren't converted, so the mode only changes, through `propose_change`, while nothing
+/// is owed in the current one. The pool is accounted apart from the rest
+/// of the contract balance, and a payout never takes what the contract's storage requires.
+/// The FT pool is only tracked: FT payouts aren't limited by it, so reward FT sent with a plain
+/// `ft_transfer` still pays out, but it doesn't count towards the pool.
+#[near_bindgen]
+impl CrossContract {
+    /// Adds the attached NEAR to the reward pool. Only reward managers can call this.
+    #[payable]
+    pub fn deposit_near_rewards(&mut self) {
+        self.assert_role(Role::RewardManager);
+        self.near_reward_pool += env::attached_deposit();
+    }
+
+    pub fn get_near_reward_pool(&self) -> U128 {
+        self.near_reward_pool.into()
+    }
+
+    pub fn get_ft_reward_pool(&self) -> U128 {
+        self.ft_reward_pool.into()
+    }
+}
+
+impl CrossContract {
+    /// Panics unless nothing is owed in the current mode: no stakes earning rewards, and no
+    /// pending, settled, vesting, treasury or undistributed bonus balance.
+    pub(crate) fn assert_nothing_owed(&self) {
+        if !self.stakes.is_empty()
+            || self.unpaid_rewards > 0
+            || self.treasury_balance > 0
+            || self.bonus_remainder > 0
+        {
+            StakingError::RewardsOutstanding.panic();
+        }
+    }
+
+    /// Whether `amount` can be paid out in the current mode.
+    pub(crate) fn can_pay(&self, amount: u128) -> bool {
+        match self.config.reward_mode {
+            RewardMode::Ft => true,
+            RewardMode::Near => {
+                let storage_cost = env::storage_byte_cost() * env::storage_usage() as u128;
+                amount <= self.near_reward_pool
+                    && amount <= env::account_balance().saturating_sub(storage_cost)
+            }
+        }
+    }
+
+    /// Sends `amount` to `receiver_id` in the current mode, taking NEAR out of the pool. The
+    /// callback of the payout has to give it back with `internal_refund_payout` if the
+    /// transfer fails.
+    pub(crate) fn internal_pay(
+        &mut self,
+        receiver_id: AccountId,
+        amount: u128,
+        memo: &str,
+    ) -> Promise {
+        match self.config.reward_mode {
+            RewardMode::Ft => {
+                self.ft_reward_pool = self.ft_reward_pool.saturating_sub(amount);
+                ftext::ft_transfer(
+                    receiver_id,
+                    amount.into(),
+                    Some(memo.to_string()),
+                    self.ft_account.clone(), // contract account id
+                    ONE_YOCTO,               // yocto NEAR to attach
+                    GAS_FOR_FT_TRANSFER,     // gas to attach
+                )
+            }
+            RewardMode::Near => {
+                self.near_reward_pool -= amount;
+                Promise::new(receiver_id).transfer(amount)
+            }
+        }
+    }
+
+    /// Returns a failed payout of `amount` made in `mode` to its pool.
+    pub(crate) fn internal_refund_payout(&mut self, mode: RewardMode, amount: u128) {
+        match mode {
+            RewardMode::Ft => self.ft_reward_pool += amount,
+            RewardMode::Near => self.near_reward_pool += amount,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use near_contract_standards::non_fungible_token::metadata::TokenMetadata;
+
+use crate::*;
+
+/// Gas attached to every call minting, moving or burning a receipt.
+pub(crate) const GAS_FOR_RECEIPT: Gas = Gas(10 * TGAS);
+/// Deposit attached to every mint to cover the storage of the receipt. The receipt contract
+/// refunds what isn't used, and refunds the storage again when the receipt is burned.
+const RECEIPT_STORAGE_DEPOSIT: Balance = 10_000_000_000_000_000_000_000;
+
+/// Methods of the NFT contract issuing stake receipts, for which this contract is a minter.
+#[ext_contract(receiptext)]
+pub trait ReceiptContract {
+    fn nft_mint_receipt(
+        &mut self,
+        token_id: TokenId,
+        token_owner_id: AccountId,
+        token_metadata: TokenMetadata,
+    ) -> Token;
+    fn nft_transfer_receipt(&mut self, token_id: TokenId, receiver_id: AccountId);
+    fn nft_burn_receipt(&mut self, token_id: TokenId);
+}
+
+/// Custodial stakes are mirrored by a soulbound receipt NFT on `config.receipt_contract_id`,
+/// if set, with the stake key as token id. Soft stakes don't get a receipt, as the staked token
+/// stays in the owner's wallet.
+impl CrossContract {
+    /// Gas needed to mint, move or burn `count` receipts.
+    pub(crate) fn gas_for_receipts(&self, count: u64) -> u64 {
+        if self.config.receipt_contract_id.is_some() {
+            count * GAS_FOR_RECEIPT.0
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn internal_mint_receipt(&self, stake: &Stake) {
+        if let Some(receipt_contract_id) = &self.config.receipt_contract_id {
+            let contract_token_id = contract_token_id(&stake.nft_contract_id, &stake.staked_id);
+            let token_metadata = TokenMetadata {
+                title: Some(format!("Staked {}", contract_token_id)),
+                description: None,
+                media: None,
+                media_hash: None,
+                copies: Some(1),
+                issued_at: Some(stake.timestamp.to_string()),
+                expires_at: None,
+                starts_at: None,
+                updated_at: None,
+                extra: None,
+                reference: None,
+                reference_hash: None,
+            };
+            receiptext::nft_mint_receipt(
+                contract_token_id,
+                stake.owner_id.clone(),
+                token_metadata,
+                receipt_contract_id.clone(),
+                RECEIPT_STORAGE_DEPOSIT,
+                GAS_FOR_RECEIPT,
+            );
+        }
+    }
+
+    pub(crate) fn internal_transfer_receipt(&self, stake: &Stake, receiver_id: &AccountId) {
+        if let Some(receipt_contract_id) = &self.config.receipt_contract_id {
+            receiptext::nft_transfer_receipt(
+                contract_token_id(&stake.nft_contract_id, &stake.staked_id),
+                receiver_id.clone(),
+                receipt_contract_id.clone(),
+                0,
+                GAS_FOR_RECEIPT,
+            );
+        }
+    }
+
+    pub(crate) fn internal_burn_receipt(&self, stake: &Stake) {
+        if let Some(receipt_contract_id) = &self.config.receipt_contract_id {
+            receiptext::nft_burn_receipt(
+                contract_token_id(&stake.nft_contract_id, &stake.staked_id),
+                receipt_contract_id.clone(),
+                0,
+                GAS_FOR_RECEIPT,
+            );
+        }
+    }
+}
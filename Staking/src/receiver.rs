@@ -0,0 +1,45 @@
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+use near_sdk::serde::Deserialize;
+use near_sdk::serde_json;
+
+use crate::*;
+
+/// Options passed as `msg` to `nft_transfer_call` or `nft_approve`, as JSON. An empty `msg`
+/// stands for no options.
+#[derive(Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeMsg {
+    /// Referrer of the staker, only taken into account on its first stake.
+    pub referrer_id: Option<AccountId>,
+}
+
+impl StakeMsg {
+    pub fn parse(msg: &str) -> Self {
+        if msg.is_empty() {
+            return Self::default();
+        }
+        serde_json::from_str(msg).unwrap_or_else(|_| StakingError::InvalidMsg.panic())
+    }
+}
+
+/// Sending an NFT with `nft_transfer_call` stakes it for its previous owner, with no need for a
+/// separate approval.
+#[near_bindgen]
+impl NonFungibleTokenReceiver for CrossContract {
+    /// Keeps the token and records the stake. Any error makes the NFT contract return the token.
+    #[allow(unused_variables)]
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let nft_contract_id = env::predecessor_account_id();
+        let StakeMsg { referrer_id } = StakeMsg::parse(&msg);
+        self.internal_set_referrer(&previous_owner_id, referrer_id);
+        self.internal_add_stake(&previous_owner_id, &nft_contract_id, &token_id, None);
+        self.internal_resolve_stake(&contract_token_id(&nft_contract_id, &token_id), true);
+        PromiseOrValue::Value(false)
+    }
+}
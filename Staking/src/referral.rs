@@ -0,0 +1,72 @@
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct ReferrerStats {
+    referees: u64,
+    earned: u128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralStatsView {
+    /// Account that referred this one, if any.
+    pub referrer_id: Option<AccountId>,
+    /// Number of accounts this one referred.
+    pub referees: u64,
+    /// Referral rewards credited to this account so far.
+    pub earned: U128,
+}
+
+/// A referrer given with an account's first stake earns `config.referral_bps` of every reward
+/// that account claims afterwards. The share comes on top of the referee's reward and is added
+/// to the referrer's pending rewards, to be paid out with their next claim.
+#[near_bindgen]
+impl CrossContract {
+    pub fn get_referral_stats(&self, account_id: AccountId) -> ReferralStatsView {
+        let stats = self.referrer_stats.get(&account_id).unwrap_or_default();
+        ReferralStatsView {
+            referrer_id: self.referrers.get(&account_id),
+            referees: stats.referees,
+            earned: stats.earned.into(),
+        }
+    }
+}
+
+impl CrossContract {
+    /// Records `referrer_id` for `account_id` if it is about to stake for the first time.
+    pub(crate) fn internal_set_referrer(
+        &mut self,
+        account_id: &AccountId,
+        referrer_id: Option<AccountId>,
+    ) {
+        let referrer_id = match referrer_id {
+            Some(referrer_id) if referrer_id != *account_id => referrer_id,
+            _ => return,
+        };
+        if self.has_staked_before(account_id) || self.referrers.get(account_id).is_some() {
+            return;
+        }
+        self.referrers.insert(account_id, &referrer_id);
+        let mut stats = self.referrer_stats.get(&referrer_id).unwrap_or_default();
+        stats.referees += 1;
+        self.referrer_stats.insert(&referrer_id, &stats);
+    }
+
+    /// Credits the referrer of `account_id`, if any, with its share of `reward`.
+    pub(crate) fn internal_pay_referral(&mut self, account_id: &AccountId, reward: u128) {
+        let referrer_id = match self.referrers.get(account_id) {
+            Some(referrer_id) => referrer_id,
+            None => return,
+        };
+        let share = reward * self.config.referral_bps as u128 / config::MAX_BPS as u128;
+        if share == 0 {
+            return;
+        }
+        let mut stats = self.referrer_stats.get(&referrer_id).unwrap_or_default();
+        stats.earned += share;
+        self.referrer_stats.insert(&referrer_id, &stats);
+        self.internal_add_pending_reward(&referrer_id, share);
+    }
+}
@@ -0,0 +1,211 @@
+use near_sdk::serde_json;
+
+use crate::*;
+
+const MEMO_RESCUE: &str = "rescue";
+/// Gas attached to the callback logging a rescue once its transfer went through.
+const GAS_FOR_RESOLVE_RESCUE: Gas = Gas(5 * TGAS);
+/// Gas attached to the callback checking the NFT owner, which then makes the transfer.
+const GAS_FOR_RESOLVE_RESCUE_NFT: Gas =
+    Gas(GAS_FOR_EXECUTION.0 + GAS_FOR_NFT_TRANSFER.0 + GAS_FOR_RESOLVE_RESCUE.0);
+/// Gas attached to the callback checking the FT balance, which then makes the transfer.
+const GAS_FOR_RESOLVE_RESCUE_FT: Gas =
+    Gas(GAS_FOR_EXECUTION.0 + GAS_FOR_FT_TRANSFER.0 + GAS_FOR_RESOLVE_RESCUE.0);
+
+/// Tokens sent to the contract outside of the staking flow, e.g. with a plain `nft_transfer` or
+/// `ft_transfer`, can be sent on by an owner. Staked NFTs can't be rescued, nor NFTs the contract
+/// is merely approved for, nor the part of the reward FT balance backing the tracked reward pool.
+/// A rescue is logged once its transfer went through.
+#[near_bindgen]
+impl CrossContract {
+    /// Sends `token_id` of `nft_contract_id`, which must not be staked, to `receiver_id`. The
+    /// owner of the token is read with `nft_token` first, and only tokens held by the contract
+    /// can be sent. Only owners can call this. Requires exactly 1 yoctoNEAR; the contract covers
+    /// the 1 yoctoNEAR that `nft_transfer` needs.
+    #[payable]
+    pub fn rescue_nft(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        receiver_id: AccountId,
+    ) -> Promise {
+        assert_one_yocto();
+        self.assert_role(Role::Owner);
+        assert_enough_gas(Gas(GAS_FOR_NFT_TOKEN.0 + GAS_FOR_RESOLVE_RESCUE_NFT.0));
+        self.assert_not_staked(&nft_contract_id, &token_id);
+        nftext::nft_token(
+            token_id.clone(),
+            nft_contract_id.clone(),
+            0,
+            GAS_FOR_NFT_TOKEN,
+        )
+        .then(ext_self::resolve_rescue_nft(
+            nft_contract_id,
+            token_id,
+            receiver_id,
+            env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_RESCUE_NFT,
+        ))
+    }
+
+    /// Sends the rescued NFT if the token read is held by the contract and still not staked.
+    #[private]
+    pub fn resolve_rescue_nft(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        receiver_id: AccountId,
+    ) -> Promise {
+        let held = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<Option<Token>>(&value)
+                .ok()
+                .flatten()
+                .filter(|token| token.owner_id == env::current_account_id())
+                .is_some(),
+            _ => false,
+        };
+        if !held {
+            StakingError::TokenNotHeld.panic();
+        }
+        self.assert_not_staked(&nft_contract_id, &token_id);
+        nftext::nft_transfer(
+            receiver_id.clone(),
+            token_id.clone(),
+            None,
+            Some(MEMO_RESCUE.to_string()),
+            nft_contract_id.clone(),
+            ONE_YOCTO,
+            GAS_FOR_NFT_TRANSFER,
+        )
+        .then(ext_self::resolve_nft_rescued(
+            nft_contract_id,
+            token_id,
+            receiver_id,
+            env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_RESCUE,
+        ))
+    }
+
+    /// Logs an `nft_rescued` event if the transfer went through.
+    #[private]
+    pub fn resolve_nft_rescued(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        receiver_id: AccountId,
+    ) -> bool {
+        let transferred = is_promise_success();
+        if transferred {
+            StakingEvent::NftRescued(&[NftRescueData {
+                nft_contract_id: &nft_contract_id,
+                token_id: &token_id,
+                receiver_id: &receiver_id,
+            }])
+            .emit();
+        }
+        transferred
+    }
+
+    /// Sends `amount` of `ft_contract_id` (the reward token if omitted) to `receiver_id`. The
+    /// balance is read with `ft_balance_of` first, and only what exceeds the reward pool can be
+    /// sent. Only owners can call this. Requires exactly 1 yoctoNEAR.
+    #[payable]
+    pub fn rescue_ft(
+        &mut self,
+        ft_contract_id: Option<AccountId>,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Promise {
+        assert_one_yocto();
+        self.assert_role(Role::Owner);
+        assert_enough_gas(Gas(GAS_FOR_FT_BALANCE_OF.0 + GAS_FOR_RESOLVE_RESCUE_FT.0));
+        let ft_contract_id = ft_contract_id.unwrap_or_else(|| self.ft_account.clone());
+        ftext::ft_balance_of(
+            env::current_account_id(),
+            ft_contract_id.clone(),
+            0,
+            GAS_FOR_FT_BALANCE_OF,
+        )
+        .then(ext_self::resolve_rescue_ft(
+            ft_contract_id,
+            receiver_id,
+            amount,
+            env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_RESCUE_FT,
+        ))
+    }
+
+    /// Sends the rescued amount if the balance read exceeds the reward pool by at least as much.
+    #[private]
+    pub fn resolve_rescue_ft(
+        &mut self,
+        ft_contract_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Promise {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<U128>(&value).ok(),
+            _ => None,
+        }
+        .unwrap_or_else(|| StakingError::BalanceUnavailable.panic());
+        let reserved = if ft_contract_id == self.ft_account {
+            self.ft_reward_pool
+        } else {
+            0
+        };
+        if amount.0 == 0 || amount.0 > balance.0.saturating_sub(reserved) {
+            StakingError::InsufficientExcess.panic();
+        }
+        ftext::ft_transfer(
+            receiver_id.clone(),
+            amount,
+            Some(MEMO_RESCUE.to_string()),
+            ft_contract_id.clone(),
+            ONE_YOCTO,
+            GAS_FOR_FT_TRANSFER,
+        )
+        .then(ext_self::resolve_ft_rescued(
+            ft_contract_id,
+            receiver_id,
+            amount,
+            env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_RESCUE,
+        ))
+    }
+
+    /// Logs an `ft_rescued` event if the transfer went through.
+    #[private]
+    pub fn resolve_ft_rescued(
+        &mut self,
+        ft_contract_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        let transferred = is_promise_success();
+        if transferred {
+            StakingEvent::FtRescued(&[FtRescueData {
+                ft_contract_id: &ft_contract_id,
+                receiver_id: &receiver_id,
+                amount,
+            }])
+            .emit();
+        }
+        transferred
+    }
+}
+
+impl CrossContract {
+    fn assert_not_staked(&self, nft_contract_id: &AccountId, token_id: &TokenId) {
+        if self
+            .stakes
+            .get(&contract_token_id(nft_contract_id, token_id))
+            .is_some()
+        {
+            StakingError::TokenAlreadyStaked.panic();
+        }
+    }
+}
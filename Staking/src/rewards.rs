@@ -0,0 +1,70 @@
+use crate::*;
+
+/// Reward rate in effect from `timestamp` on, until the next checkpoint, along with the reward
+/// a staked token had accrued by then.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RateCheckpoint {
+    timestamp: u64,
+    reward_per_stake: u128,
+    rate: u128,
+}
+
+/// Reward accrued by a token staked over `elapsed` nanoseconds at `rate`.
+fn accrued_at_rate(rate: u128, elapsed: u64) -> u128 {
+    let elapsed = elapsed as u128;
+    // Split into whole seconds and the remainder so the multiplication can't overflow.
+    rate * (elapsed / NANOS_PER_SECOND) + rate * (elapsed % NANOS_PER_SECOND) / NANOS_PER_SECOND
+}
+
+/// Every change of the reward rate is checkpointed with the reward a staked token had accrued
+/// up to it. The reward of a stake between two times is then the difference of the running
+/// totals at those times, so a new rate only applies from the moment it is set.
+impl CrossContract {
+    /// Reward accrued by a token staked from the start up to `timestamp`.
+    pub(crate) fn reward_per_stake_at(&self, timestamp: u64) -> u128 {
+        let checkpoints = &self.rate_checkpoints;
+        // Binary search for the last checkpoint at or before `timestamp`.
+        let (mut low, mut high) = (0, checkpoints.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if checkpoints.get(mid).unwrap().timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let checkpoint = checkpoints.get(low.saturating_sub(1)).unwrap();
+        checkpoint.reward_per_stake
+            + accrued_at_rate(
+                checkpoint.rate,
+                timestamp.saturating_sub(checkpoint.timestamp),
+            )
+    }
+
+    /// Records that `config.reward_rate` applies from now on.
+    pub(crate) fn internal_checkpoint_rate(&mut self) {
+        let now = env::block_timestamp();
+        let checkpoint = RateCheckpoint {
+            timestamp: now,
+            reward_per_stake: self.reward_per_stake_at(now),
+            rate: self.config.reward_rate.0,
+        };
+        let last = self.rate_checkpoints.len() - 1;
+        if self.rate_checkpoints.get(last).unwrap().timestamp == now {
+            self.rate_checkpoints.replace(last, &checkpoint);
+        } else {
+            self.rate_checkpoints.push(&checkpoint);
+        }
+    }
+
+    /// Checkpoints for a new contract, with `rate` in effect from the start.
+    pub(crate) fn initial_rate_checkpoints(rate: u128) -> Vector<RateCheckpoint> {
+        let mut checkpoints = Vector::new(StorageKey::RateCheckpoints);
+        checkpoints.push(&RateCheckpoint {
+            timestamp: 0,
+            reward_per_stake: 0,
+            rate,
+        });
+        checkpoints
+    }
+}
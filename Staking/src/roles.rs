@@ -0,0 +1,130 @@
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Roles gating the admin methods. `owner_id` always holds [`Role::Owner`], and owners pass
+/// every role check.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Grants and revokes roles, upgrades the contract and withdraws the treasury.
+    Owner,
+    /// Manages rewards: the reward parameters and the set bonuses.
+    RewardManager,
+    /// Pauses and unpauses the contract.
+    Pauser,
+    /// Runs day-to-day operations: the rest of the config and the deny and block lists.
+    Operator,
+}
+
+#[near_bindgen]
+impl CrossContract {
+    /// Grants `role` to `account_id`. Only owners can call this.
+    #[payable]
+    pub fn grant_role(&mut self, account_id: AccountId, role: Role) {
+        assert_one_yocto();
+        self.assert_role(Role::Owner);
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if !roles.contains(&role) {
+            roles.push(role);
+            self.roles.insert(&account_id, &roles);
+            StakingEvent::RoleGranted(&[RoleData {
+                account_id: &account_id,
+                role,
+            }])
+            .emit();
+        }
+    }
+
+    /// Revokes `role` from `account_id`. Only owners can call this, and `owner_id` keeps
+    /// [`Role::Owner`] regardless.
+    #[payable]
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role) {
+        assert_one_yocto();
+        self.assert_role(Role::Owner);
+        let mut roles = self.roles.get(&account_id).unwrap_or_default();
+        if roles.contains(&role) {
+            roles.retain(|r| *r != role);
+            if roles.is_empty() {
+                self.roles.remove(&account_id);
+            } else {
+                self.roles.insert(&account_id, &roles);
+            }
+            StakingEvent::RoleRevoked(&[RoleData {
+                account_id: &account_id,
+                role,
+            }])
+            .emit();
+        }
+    }
+
+    /// Whether `account_id` holds `role` itself, not counting what owners are allowed.
+    pub fn has_role(&self, account_id: AccountId, role: Role) -> bool {
+        (role == Role::Owner && account_id == self.owner_id)
+            || self
+                .roles
+                .get(&account_id)
+                .filter(|roles| roles.contains(&role))
+                .is_some()
+    }
+
+    /// Stops staking, claiming, stake transfers and withdrawals of vested rewards until
+    /// `unpause`. Unstaking stays open. Only pausers can call this.
+    #[payable]
+    pub fn pause(&mut self) {
+        assert_one_yocto();
+        self.assert_role(Role::Pauser);
+        if !self.paused {
+            self.paused = true;
+            StakingEvent::Paused(&[AccountData {
+                account_id: &env::predecessor_account_id(),
+            }])
+            .emit();
+        }
+    }
+
+    /// Only pausers can call this.
+    #[payable]
+    pub fn unpause(&mut self) {
+        assert_one_yocto();
+        self.assert_role(Role::Pauser);
+        if self.paused {
+            self.paused = false;
+            StakingEvent::Unpaused(&[AccountData {
+                account_id: &env::predecessor_account_id(),
+            }])
+            .emit();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+impl CrossContract {
+    /// Panics with [`StakingError::Unauthorized`] unless the caller holds `role` or is an owner.
+    pub(crate) fn assert_role(&self, role: Role) {
+        self.assert_account_role(&env::predecessor_account_id(), role);
+    }
+
+    /// Panics with [`StakingError::Unauthorized`] unless `account_id` holds `role` or is an
+    /// owner.
+    pub(crate) fn assert_account_role(&self, account_id: &AccountId, role: Role) {
+        if !self.has_role(account_id.clone(), role)
+            && !self.has_role(account_id.clone(), Role::Owner)
+        {
+            StakingError::Unauthorized.panic();
+        }
+    }
+
+    /// Panics with [`StakingError::Paused`] while the contract is paused.
+    pub(crate) fn assert_not_paused(&self) {
+        if self.paused {
+            StakingError::Paused.panic();
+        }
+    }
+}
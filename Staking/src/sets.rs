@@ -0,0 +1,334 @@
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, Value};
+
+use crate::*;
+
+/// Gas attached to the callback recording the traits of a staked token, besides the refresh of
+/// its owner's active sets.
+const GAS_FOR_RESOLVE_TOKEN_TRAITS: Gas = Gas(5 * TGAS);
+/// Gas to check one stake against one set while refreshing an account's active sets.
+const GAS_FOR_SET_MEMBER_CHECK: Gas = Gas(TGAS / 10);
+
+/// Tokens of one collection that together make up a set.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SetMembers {
+    /// Exactly these tokens.
+    TokenIds(Vec<TokenId>),
+    /// Any `size` tokens whose metadata `extra`, a JSON object, maps `trait_type` to `value`.
+    Trait {
+        trait_type: String,
+        value: String,
+        size: u32,
+    },
+}
+
+/// A set of tokens whose stakes earn `bonus_bps` more while an account has all of them staked.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeSet {
+    pub nft_contract_id: AccountId,
+    pub members: SetMembers,
+    /// Extra reward of every member while the set is complete, in basis points.
+    pub bonus_bps: u16,
+}
+
+impl StakeSet {
+    fn assert_valid(&self) {
+        let empty = match &self.members {
+            SetMembers::TokenIds(token_ids) => token_ids.is_empty(),
+            SetMembers::Trait { size, .. } => *size == 0,
+        };
+        if empty {
+            StakingError::InvalidConfig("a set must have members").panic();
+        }
+        if self.bonus_bps > config::MAX_BPS {
+            StakingError::InvalidConfig("bonus_bps can't exceed 10000").panic();
+        }
+    }
+
+    /// Whether `token` has the trait of a trait set.
+    fn has_trait(&self, token: &Token) -> bool {
+        let (trait_type, value) = match &self.members {
+            SetMembers::Trait {
+                trait_type, value, ..
+            } => (trait_type, value),
+            SetMembers::TokenIds(_) => return false,
+        };
+        token
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.extra.as_ref())
+            .and_then(|extra| serde_json::from_str::<Value>(extra).ok())
+            .and_then(|extra| {
+                extra
+                    .get(trait_type)
+                    .and_then(Value::as_str)
+                    .map(str::to_owned)
+            })
+            .as_ref()
+            == Some(value)
+    }
+}
+
+/// The owner names sets of tokens, either by token id or by a metadata trait. An account with
+/// every member of a set under confirmed custodial stake earns `bonus_bps` more on those stakes
+/// until one of them is unstaked. Soft stakes don't count, as they are only checked on claim.
+/// The traits of a token are read with `nft_token` once its stake is resolved, and can be read
+/// again with `refresh_token_traits`, e.g. for trait sets added later.
+///
+/// Each account keeps the definitions of its complete sets as of their last refresh. Whenever
+/// they change, the rewards its stakes accrued so far are settled at the bonus that applied, so
+/// a bonus is never granted or taken away retroactively. Changes to the sets themselves reach
+/// an account on its next stake, unstake, claim or `refresh_active_sets`.
+#[near_bindgen]
+impl CrossContract {
+    /// Adds the set `name`, up to `max_sets` sets, or replaces it. Only reward managers can call
+    /// this.
+    #[payable]
+    pub fn set_stake_set(&mut self, name: String, set: StakeSet) {
+        assert_one_yocto();
+        self.assert_role(Role::RewardManager);
+        set.assert_valid();
+        if self.sets.get(&name).is_none() && self.sets.len() >= self.config.max_sets as u64 {
+            StakingError::InvalidConfig("the number of sets can't exceed max_sets").panic();
+        }
+        self.sets.insert(&name, &set);
+    }
+
+    /// Removes the set `name`. Only reward managers can call this.
+    #[payable]
+    pub fn remove_stake_set(&mut self, name: String) {
+        assert_one_yocto();
+        self.assert_role(Role::RewardManager);
+        self.sets.remove(&name);
+    }
+
+    pub fn get_stake_sets(&self) -> Vec<(String, StakeSet)> {
+        self.sets.to_vec()
+    }
+
+    /// Names of the sets `account_id` has complete, earning their bonus.
+    pub fn get_active_sets(&self, account_id: AccountId) -> Vec<String> {
+        self.active_sets
+            .get(&account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Applies the current sets to `account_id`, settling its rewards at the bonus of its
+    /// previous sets if they changed.
+    pub fn refresh_active_sets(&mut self, account_id: AccountId) {
+        self.internal_refresh_active_sets(&account_id);
+    }
+
+    /// Reads the traits of the custodial stakes of `token_ids` of `nft_contract_id`
+    /// (`nft_account` if omitted) again, for trait sets added or changed since they were staked.
+    pub fn refresh_token_traits(
+        &mut self,
+        token_ids: Vec<TokenId>,
+        nft_contract_id: Option<AccountId>,
+    ) {
+        if token_ids.is_empty() {
+            StakingError::EmptyBatch.panic();
+        }
+        let nft_contract_id = nft_contract_id.unwrap_or_else(|| self.nft_account.clone());
+        assert_enough_gas(Gas(
+            self.gas_for_token_traits(&nft_contract_id, token_ids.len() as u64)
+        ));
+        for token_id in token_ids {
+            let stake = self
+                .stakes
+                .get(&contract_token_id(&nft_contract_id, &token_id))
+                .filter(|stake| stake.approval_id.is_none() && stake.status != StakeStatus::Pending)
+                .unwrap_or_else(|| StakingError::TokenNotStaked.panic());
+            self.internal_fetch_token_traits(&stake);
+        }
+    }
+
+    /// Records which trait sets the staked token belongs to, replacing what was read before.
+    #[private]
+    pub fn resolve_token_traits(&mut self, contract_token_id: ContractTokenId) {
+        let token = match env::promise_result(0) {
+            PromiseResult::Successful(value) => serde_json::from_slice::<Option<Token>>(&value)
+                .ok()
+                .flatten(),
+            _ => None,
+        };
+        let (token, stake) = match (token, self.stakes.get(&contract_token_id)) {
+            (Some(token), Some(stake)) => (token, stake),
+            _ => return,
+        };
+        let names: Vec<String> = self
+            .sets
+            .iter()
+            .filter(|(_, set)| {
+                set.nft_contract_id == stake.nft_contract_id && set.has_trait(&token)
+            })
+            .map(|(name, _)| name)
+            .collect();
+        if names.is_empty() {
+            self.token_traits.remove(&contract_token_id);
+        } else {
+            self.token_traits.insert(&contract_token_id, &names);
+        }
+        self.internal_refresh_active_sets(&stake.owner_id);
+    }
+}
+
+impl CrossContract {
+    fn has_trait_sets(&self, nft_contract_id: &AccountId) -> bool {
+        self.sets.values().any(|set| {
+            set.nft_contract_id == *nft_contract_id
+                && matches!(set.members, SetMembers::Trait { .. })
+        })
+    }
+
+    /// Gas needed to read the traits of `count` tokens of `nft_contract_id` once staked.
+    pub(crate) fn gas_for_token_traits(&self, nft_contract_id: &AccountId, count: u64) -> u64 {
+        if self.has_trait_sets(nft_contract_id) {
+            count * (GAS_FOR_NFT_TOKEN.0 + self.gas_for_resolve_token_traits().0)
+        } else {
+            0
+        }
+    }
+
+    fn gas_for_resolve_token_traits(&self) -> Gas {
+        Gas(GAS_FOR_RESOLVE_TOKEN_TRAITS.0 + self.gas_for_set_refresh())
+    }
+
+    /// Gas a refresh of an account's active sets needs at most, with as many stakes as an
+    /// account can have.
+    pub(crate) fn gas_for_set_refresh(&self) -> u64 {
+        self.sets.len() * self.config.max_stakes_per_account as u64 * GAS_FOR_SET_MEMBER_CHECK.0
+    }
+
+    /// Reads the traits of a staked token, if its collection has trait sets.
+    pub(crate) fn internal_fetch_token_traits(&self, stake: &Stake) {
+        if self.has_trait_sets(&stake.nft_contract_id) {
+            nftext::nft_token(
+                stake.staked_id.clone(),
+                stake.nft_contract_id.clone(),
+                0,
+                GAS_FOR_NFT_TOKEN,
+            )
+            .then(ext_self::resolve_token_traits(
+                contract_token_id(&stake.nft_contract_id, &stake.staked_id),
+                env::current_account_id(),
+                0,
+                self.gas_for_resolve_token_traits(),
+            ));
+        }
+    }
+
+    /// Whether `stake` counts towards the set `name`.
+    fn is_set_member(&self, name: &str, set: &StakeSet, stake: &Stake) -> bool {
+        if stake.approval_id.is_some()
+            || stake.status == StakeStatus::Pending
+            || stake.nft_contract_id != set.nft_contract_id
+        {
+            return false;
+        }
+        match &set.members {
+            SetMembers::TokenIds(token_ids) => token_ids.contains(&stake.staked_id),
+            SetMembers::Trait { .. } => self
+                .token_traits
+                .get(&contract_token_id(&stake.nft_contract_id, &stake.staked_id))
+                .filter(|names| names.iter().any(|n| n == name))
+                .is_some(),
+        }
+    }
+
+    fn is_set_complete(&self, account_id: &AccountId, name: &str, set: &StakeSet) -> bool {
+        let stakes = self.internal_stakes_list(account_id);
+        match &set.members {
+            SetMembers::TokenIds(token_ids) => token_ids.iter().all(|token_id| {
+                stakes.iter().any(|stake| {
+                    stake.staked_id == *token_id && self.is_set_member(name, set, stake)
+                })
+            }),
+            SetMembers::Trait { size, .. } => {
+                stakes
+                    .iter()
+                    .filter(|stake| self.is_set_member(name, set, stake))
+                    .count()
+                    >= *size as usize
+            }
+        }
+    }
+
+    fn internal_stakes_list(&self, account_id: &AccountId) -> Vec<Stake> {
+        self.stakes_per_owner
+            .get(account_id)
+            .map_or_else(Vec::new, |token_ids| {
+                token_ids
+                    .iter()
+                    .map(|id| self.stakes.get(&id).unwrap())
+                    .collect()
+            })
+    }
+
+    /// Extra reward of `stake`, in basis points, from the active sets of its owner as they were
+    /// defined when last refreshed.
+    pub(crate) fn internal_set_bonus_bps(&self, stake: &Stake) -> u32 {
+        self.active_sets
+            .get(&stake.owner_id)
+            .unwrap_or_default()
+            .iter()
+            .filter(|(name, set)| self.is_set_member(name, set, stake))
+            .map(|(_, set)| set.bonus_bps as u32)
+            .sum()
+    }
+
+    /// Moves what the custodial stakes of `account_id` accrued so far, at their current bonus,
+    /// to its settled rewards, which the next claim pays out like accrued ones.
+    fn internal_settle_rewards(&mut self, account_id: &AccountId) {
+        let now = env::block_timestamp();
+        let mut settled = 0;
+        for mut stake in self.internal_stakes_list(account_id) {
+            if stake.approval_id.is_some() {
+                continue;
+            }
+            settled += self.internal_accrued(&stake, now);
+            stake.claimed_at = now;
+            self.stakes.insert(
+                &contract_token_id(&stake.nft_contract_id, &stake.staked_id),
+                &stake,
+            );
+        }
+        if settled > 0 {
+            let total = self.settled_rewards.get(account_id).unwrap_or(0) + settled;
+            self.settled_rewards.insert(account_id, &total);
+            self.unpaid_rewards += settled;
+        }
+    }
+
+    /// Settles the rewards of `stake`'s owner if it is about to leave an active set.
+    pub(crate) fn internal_settle_before_removal(&mut self, stake: &Stake) {
+        if self.internal_set_bonus_bps(stake) > 0 {
+            self.internal_settle_rewards(&stake.owner_id);
+        }
+    }
+
+    /// Recomputes the complete sets of `account_id` from the current sets, settling its rewards
+    /// first if they, or their definitions, changed.
+    pub(crate) fn internal_refresh_active_sets(&mut self, account_id: &AccountId) {
+        let active: Vec<(String, StakeSet)> = self
+            .sets
+            .iter()
+            .filter(|(name, set)| self.is_set_complete(account_id, name, set))
+            .collect();
+        if active == self.active_sets.get(account_id).unwrap_or_default() {
+            return;
+        }
+        self.internal_settle_rewards(account_id);
+        if active.is_empty() {
+            self.active_sets.remove(account_id);
+        } else {
+            self.active_sets.insert(account_id, &active);
+        }
+    }
+}
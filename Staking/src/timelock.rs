@@ -0,0 +1,130 @@
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// A config change that has to wait for `config.timelock_delay` before it applies.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChange {
+    RewardRate(U128),
+    ClaimFeeBps(u16),
+    InstantClaimPenaltyBps(u16),
+    ReferralBps(u16),
+    RewardMode(RewardMode),
+    TimelockDelay(U64),
+}
+
+impl ConfigChange {
+    /// Role needed to propose, execute or cancel the change.
+    fn role(&self) -> Role {
+        match self {
+            ConfigChange::TimelockDelay(_) => Role::Owner,
+            _ => Role::RewardManager,
+        }
+    }
+
+    fn apply(&self, config: &mut Config) {
+        match self {
+            ConfigChange::RewardRate(reward_rate) => config.reward_rate = *reward_rate,
+            ConfigChange::ClaimFeeBps(claim_fee_bps) => config.claim_fee_bps = *claim_fee_bps,
+            ConfigChange::InstantClaimPenaltyBps(penalty_bps) => {
+                config.instant_claim_penalty_bps = *penalty_bps
+            }
+            ConfigChange::ReferralBps(referral_bps) => config.referral_bps = *referral_bps,
+            ConfigChange::RewardMode(reward_mode) => config.reward_mode = *reward_mode,
+            ConfigChange::TimelockDelay(timelock_delay) => config.timelock_delay = *timelock_delay,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingChange {
+    pub id: u64,
+    pub change: ConfigChange,
+    /// Time from which the change can be executed.
+    pub executable_at: U64,
+}
+
+/// Changes to the reward rate, the claim fee, the instant claim penalty, the referral share, the
+/// reward mode and the timelock delay itself are announced by proposing them, and can only be
+/// executed once the delay has passed, giving stakers time to react. Until then they can be
+/// cancelled.
+#[near_bindgen]
+impl CrossContract {
+    /// Queues `change`, returning its id. Reward managers can propose changes to the reward
+    /// parameters, owners delay changes.
+    #[payable]
+    pub fn propose_change(&mut self, change: ConfigChange) -> u64 {
+        assert_one_yocto();
+        self.assert_role(change.role());
+        let mut config = self.config.clone();
+        change.apply(&mut config);
+        config.assert_valid();
+        self.assert_change_allowed(&change);
+        let pending = PendingChange {
+            id: self.next_change_id,
+            change,
+            executable_at: U64(env::block_timestamp() + self.config.timelock_delay.0),
+        };
+        self.next_change_id += 1;
+        self.pending_changes.insert(&pending.id, &pending);
+        StakingEvent::ChangeProposed(&[&pending]).emit();
+        pending.id
+    }
+
+    /// Applies the pending change `id` once its delay has passed.
+    #[payable]
+    pub fn execute_change(&mut self, id: u64) {
+        assert_one_yocto();
+        let pending = self.internal_pending_change(id);
+        self.assert_role(pending.change.role());
+        if env::block_timestamp() < pending.executable_at.0 {
+            StakingError::TimelockNotExpired.panic();
+        }
+        self.pending_changes.remove(&id);
+        let mut config = self.config.clone();
+        pending.change.apply(&mut config);
+        config.assert_valid();
+        self.assert_change_allowed(&pending.change);
+        self.config = config;
+        if let ConfigChange::RewardRate(_) = pending.change {
+            self.internal_checkpoint_rate();
+        }
+        StakingEvent::ChangeExecuted(&[&pending]).emit();
+    }
+
+    /// Drops the pending change `id`.
+    #[payable]
+    pub fn cancel_change(&mut self, id: u64) {
+        assert_one_yocto();
+        let pending = self.internal_pending_change(id);
+        self.assert_role(pending.change.role());
+        self.pending_changes.remove(&id);
+        StakingEvent::ChangeCancelled(&[&pending]).emit();
+    }
+
+    pub fn get_pending_changes(&self) -> Vec<PendingChange> {
+        self.pending_changes.values().collect()
+    }
+}
+
+impl CrossContract {
+    /// Switching the reward mode requires that nothing is owed in the current one, when it is
+    /// proposed and again when it is executed.
+    fn assert_change_allowed(&self, change: &ConfigChange) {
+        if let ConfigChange::RewardMode(reward_mode) = change {
+            if *reward_mode != self.config.reward_mode {
+                self.assert_nothing_owed();
+            }
+        }
+    }
+
+    fn internal_pending_change(&self, id: u64) -> PendingChange {
+        self.pending_changes
+            .get(&id)
+            .unwrap_or_else(|| StakingError::ChangeNotFound.panic())
+    }
+}
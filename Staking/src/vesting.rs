@@ -0,0 +1,151 @@
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+const MEMO_VESTED: &str = "vested";
+
+/// Claimed rewards unlocking linearly over `duration` from `start`, with nothing unlocked before
+/// `start + cliff`. The terms are copied from the config at claim time.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingSchedule {
+    pub amount: U128,
+    pub withdrawn: U128,
+    pub start: U64,
+    pub cliff: U64,
+    pub duration: U64,
+}
+
+impl VestingSchedule {
+    /// Part of `amount` unlocked at `now`.
+    fn vested(&self, now: u64) -> u128 {
+        let elapsed = now.saturating_sub(self.start.0);
+        if elapsed < self.cliff.0 {
+            0
+        } else if elapsed >= self.duration.0 {
+            self.amount.0
+        } else {
+            let (amount, elapsed, duration) =
+                (self.amount.0, elapsed as u128, self.duration.0 as u128);
+            // Split the amount so the multiplication can't overflow.
+            amount / duration * elapsed + amount % duration * elapsed / duration
+        }
+    }
+
+    fn withdrawable(&self, now: u64) -> u128 {
+        self.vested(now) - self.withdrawn.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VestingView {
+    /// Unlocked rewards that can be withdrawn right now.
+    pub withdrawable: U128,
+    pub schedules: Vec<VestingSchedule>,
+}
+
+#[near_bindgen]
+impl CrossContract {
+    /// Withdraws every unlocked part of the caller's vesting rewards. Requires exactly 1
+    /// yoctoNEAR, which is forwarded to `ft_transfer`.
+    #[payable]
+    pub fn withdraw_vested(&mut self) -> Promise {
+        assert_one_yocto();
+        assert_enough_gas(GAS_FOR_PAYOUT);
+        self.assert_not_paused();
+        let caller = env::predecessor_account_id();
+        self.assert_not_denied(&caller);
+        let now = env::block_timestamp();
+        let mut schedules = self.vesting.get(&caller).unwrap_or_default();
+        let mut amount = 0;
+        for schedule in schedules.iter_mut() {
+            let withdrawable = schedule.withdrawable(now);
+            schedule.withdrawn.0 += withdrawable;
+            amount += withdrawable;
+        }
+        if amount == 0 {
+            StakingError::NothingToClaim.panic();
+        }
+        if !self.can_pay(amount) {
+            StakingError::InsufficientRewardPool.panic();
+        }
+        schedules.retain(|schedule| schedule.withdrawn != schedule.amount);
+        self.unpaid_rewards -= amount;
+        self.internal_set_vesting(&caller, schedules);
+        self.internal_pay(caller.clone(), amount, MEMO_VESTED).then(
+            ext_self::resolve_withdraw_vested(
+                caller,
+                amount.into(),
+                self.config.reward_mode,
+                env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ),
+        )
+    }
+
+    /// Gives the withdrawn amount back, fully unlocked, if the FT transfer failed.
+    #[private]
+    pub fn resolve_withdraw_vested(
+        &mut self,
+        owner_id: AccountId,
+        amount: U128,
+        mode: RewardMode,
+    ) -> bool {
+        let transferred = is_promise_success();
+        if !transferred {
+            self.internal_refund_payout(mode, amount.0);
+            self.internal_add_vesting(&owner_id, amount.0, 0, 0);
+        }
+        transferred
+    }
+
+    pub fn get_vesting(&self, account_id: AccountId) -> VestingView {
+        let now = env::block_timestamp();
+        let schedules = self.vesting.get(&account_id).unwrap_or_default();
+        let withdrawable = schedules
+            .iter()
+            .map(|schedule| schedule.withdrawable(now))
+            .sum();
+        VestingView {
+            withdrawable: U128(withdrawable),
+            schedules,
+        }
+    }
+}
+
+impl CrossContract {
+    pub(crate) fn is_vesting_enabled(&self) -> bool {
+        self.config.vesting_duration.0 > 0
+    }
+
+    /// Adds `amount` to the vesting rewards of `account_id`, unlocking from now on.
+    pub(crate) fn internal_add_vesting(
+        &mut self,
+        account_id: &AccountId,
+        amount: u128,
+        cliff: u64,
+        duration: u64,
+    ) {
+        let mut schedules = self.vesting.get(account_id).unwrap_or_default();
+        schedules.push(VestingSchedule {
+            amount: U128(amount),
+            withdrawn: U128(0),
+            start: U64(env::block_timestamp()),
+            cliff: U64(cliff),
+            duration: U64(duration),
+        });
+        self.unpaid_rewards += amount;
+        self.internal_set_vesting(account_id, schedules);
+    }
+
+    fn internal_set_vesting(&mut self, account_id: &AccountId, schedules: Vec<VestingSchedule>) {
+        if schedules.is_empty() {
+            self.vesting.remove(account_id);
+        } else {
+            self.vesting.insert(account_id, &schedules);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use near_sdk::json_types::U64;
+
+use crate::*;
+
+/// Voting power held from `block_height` on, until the next checkpoint.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Checkpoint {
+    block_height: u64,
+    votes: u64,
+}
+
+/// Appends a checkpoint for the current block, replacing the last one if it was written in the
+/// same block.
+fn push_checkpoint(checkpoints: &mut Vector<Checkpoint>, votes: u64) {
+    let checkpoint = Checkpoint {
+        block_height: env::block_height(),
+        votes,
+    };
+    match checkpoints.len().checked_sub(1) {
+        Some(last) if checkpoints.get(last).unwrap().block_height == checkpoint.block_height => {
+            checkpoints.replace(last, &checkpoint);
+        }
+        _ => checkpoints.push(&checkpoint),
+    }
+}
+
+/// Votes of the last checkpoint.
+fn last_votes(checkpoints: &Vector<Checkpoint>) -> u64 {
+    checkpoints
+        .len()
+        .checked_sub(1)
+        .map_or(0, |last| checkpoints.get(last).unwrap().votes)
+}
+
+/// Votes of the last checkpoint at or before `block_height`, by binary search.
+fn votes_at(checkpoints: &Vector<Checkpoint>, block_height: u64) -> u64 {
+    let (mut low, mut high) = (0, checkpoints.len());
+    while low < high {
+        let mid = (low + high) / 2;
+        if checkpoints.get(mid).unwrap().block_height <= block_height {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low.checked_sub(1)
+        .map_or(0, |index| checkpoints.get(index).unwrap().votes)
+}
+
+/// Every staked token carries one vote, from the moment it has reached the contract or, for a
+/// soft stake, was approved. Checkpoints keyed by block height record the votes of each account
+/// and in total, so that a DAO can read them as of the block a proposal was made. As they are
+/// only written for confirmed stakes, and at most once per block, they grow no faster than
+/// tokens actually change hands.
+#[near_bindgen]
+impl CrossContract {
+    /// Votes held by `account_id` at the end of `block_height`.
+    pub fn get_voting_power(&self, account_id: AccountId, block_height: U64) -> U64 {
+        self.voting_checkpoints
+            .get(&account_id)
+            .map_or(0, |checkpoints| votes_at(&checkpoints, block_height.0))
+            .into()
+    }
+
+    /// Votes of all stakers at the end of `block_height`.
+    pub fn get_total_voting_power(&self, block_height: U64) -> U64 {
+        votes_at(&self.total_voting_checkpoints, block_height.0).into()
+    }
+}
+
+impl CrossContract {
+    /// Whether `account_id` ever had a stake.
+    pub(crate) fn has_staked_before(&self, account_id: &AccountId) -> bool {
+        self.voting_checkpoints.get(account_id).is_some()
+    }
+
+    /// Confirmed stakes of `account_id`, one vote each.
+    pub(crate) fn internal_votes(&self, account_id: &AccountId) -> u64 {
+        self.voting_checkpoints
+            .get(account_id)
+            .map_or(0, |checkpoints| last_votes(&checkpoints))
+    }
+
+    /// Confirmed stakes of all accounts.
+    pub(crate) fn internal_total_votes(&self) -> u64 {
+        last_votes(&self.total_voting_checkpoints)
+    }
+
+    /// Records a vote `gained` or lost by `account_id`, and the new total.
+    pub(crate) fn internal_checkpoint_votes(&mut self, account_id: &AccountId, gained: bool) {
+        let vote = |votes: u64| if gained { votes + 1 } else { votes - 1 };
+        let mut checkpoints = self.voting_checkpoints.get(account_id).unwrap_or_else(|| {
+            Vector::new(StorageKey::VotingCheckpointsInner {
+                account_hash: env::sha256(account_id.as_bytes()),
+            })
+        });
+        let votes = vote(last_votes(&checkpoints));
+        push_checkpoint(&mut checkpoints, votes);
+        self.voting_checkpoints.insert(account_id, &checkpoints);
+        let total = vote(last_votes(&self.total_voting_checkpoints));
+        push_checkpoint(&mut self.total_voting_checkpoints, total);
+    }
+}
@@ -82,7 +82,7 @@ pub async fn init(
     println!("***************************************************** 5");
     let res = staking_contract
         .call(&worker, "new")
-        .args_json((ft_contract.id(), nft_contract.id()))?
+        .args_json((staking_contract.id(), ft_contract.id(), nft_contract.id(), Option::<()>::None))?
         .gas(300_000_000_000_000)
         .transact()
         .await?;
@@ -288,8 +288,9 @@ async fn test_nft() -> anyhow::Result<()>  {
     
     let res = bob
         .call(&worker, staking_contract_account_id.clone(), "stake")
-        .args_json((TOKEN_ID,))?
+        .args_json((TOKEN_ID, Option::<String>::None))?
         .gas(300_000_000_000_000)
+        .deposit(ONE_YOCTO)
         .transact()
         .await?;
 
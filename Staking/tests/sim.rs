@@ -64,7 +64,7 @@ fn init() -> (UserAccount,
         contract_id: "staking_contract",
         bytes: &TOKEN_STAKING,
         signer_account: root,
-        init_method: new(ft_account.account_id(), nft_account.account_id())
+        init_method: new(root.account_id(), ft_account.account_id(), nft_account.account_id(), None)
     };
     (root, alice, bob, ft_account, nft_account, staking_account)
 }
@@ -145,8 +145,8 @@ fn test_sim_transfer() {
     // );
     let res = call!(
         bob,
-        staking_account.stake("0".parse().unwrap()),
-        0,
+        staking_account.stake("0".parse().unwrap(), None),
+        1,
         DEFAULT_GAS
     );
     println!("");